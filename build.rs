@@ -0,0 +1,4 @@
+fn main() {
+    //the migrations are embedded with sqlx::migrate!
+    println!("cargo:rerun-if-changed=migrations");
+}
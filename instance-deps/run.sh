@@ -2,6 +2,7 @@
 
 /usr/bin/socat tcp-listen:8888,fork,reuseaddr vsock-connect:7:8888 & # for the rpc server
 /usr/bin/socat vsock-listen:8889,fork,reuseaddr TCP4:mysql.mysql:5432 & # for the db
+/usr/bin/socat tcp-listen:8891,fork,reuseaddr vsock-connect:7:8891 & # for the metrics
 
 EIF_PATH=/home/tee-server.eif
 ENCLAVE_CPU_COUNT=6
@@ -1,3 +1,5 @@
+-- matches the setup.sql that databases were created with before migrations, so it is a
+-- no-op on those
 CREATE TABLE IF NOT EXISTS proofs ( 
     request_id UUID PRIMARY KEY,
     proof_type SMALLINT NOT NULL,
@@ -12,9 +14,16 @@ CREATE TABLE IF NOT EXISTS proofs (
     endpoint VARCHAR(128),
     public_inputs TEXT[],
     reason TEXT, 
-    identifier VARCHAR(255)
+    identifier VARCHAR(255),
+    attempts INTEGER NOT NULL DEFAULT 1,
+    error_code INTEGER,
+    error_data JSON
 );
 
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS error_code INTEGER;
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS error_data JSON;
+
 CREATE OR REPLACE FUNCTION status_update_notify() RETURNS trigger AS $$
 DECLARE
   notification_payload JSON;
@@ -34,7 +43,10 @@ BEGIN
       'endpoint', NEW.endpoint,
       'public_inputs', NEW.public_inputs,
       'reason', NEW.reason,
-      'identifier', NEW.identifier
+      'identifier', NEW.identifier,
+      'attempts', NEW.attempts,
+      'error_code', NEW.error_code,
+      'error_data', NEW.error_data
     );
 
     PERFORM pg_notify('status_update', notification_payload::text);
@@ -0,0 +1,2 @@
+-- sha-256 of the encrypted submit_request payload, to recognise retried submissions
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS payload_digest BYTEA;
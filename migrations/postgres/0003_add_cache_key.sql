@@ -0,0 +1,5 @@
+-- salted hash of the circuit, zkey and inputs of proofs that may be reused
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS cache_key BYTEA;
+-- MAC of the enclave over the cache key and the proof, checked before the proof is reused
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS cache_seal BYTEA;
+CREATE INDEX IF NOT EXISTS proofs_cache_key ON proofs (cache_key) WHERE cache_key IS NOT NULL;
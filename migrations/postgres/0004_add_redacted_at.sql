@@ -0,0 +1,3 @@
+-- set when the retention task clears the proof and request details
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS redacted_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS proofs_proof_type_created_at ON proofs (proof_type, created_at);
@@ -0,0 +1,3 @@
+-- proofs are looked up by the identifier of the verifying app, with the key it handed out
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS identifier_key_hash BYTEA;
+CREATE INDEX IF NOT EXISTS proofs_identifier ON proofs (identifier) WHERE identifier IS NOT NULL;
@@ -0,0 +1,36 @@
+-- the public inputs keyed by signal name, with the decoded revealed data of disclose proofs
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS public_signals JSON;
+
+CREATE OR REPLACE FUNCTION status_update_notify() RETURNS trigger AS $$
+DECLARE
+  notification_payload JSON;
+BEGIN
+  IF (TG_OP = 'UPDATE' AND NEW.status IS DISTINCT FROM OLD.status) OR TG_OP = 'INSERT' THEN
+    -- notifications are limited to 8000 bytes, so the public signals, which repeat the
+    -- public inputs with the decoded revealed data, are left for the listener to read
+    notification_payload = json_build_object(
+      'request_id', NEW.request_id,
+      'proof_type', NEW.proof_type,
+      'status', NEW.status,
+      'created_at', NEW.created_at,
+      'circuit_name', NEW.circuit_name,
+      'onchain', NEW.onchain, 
+      'witness_generated_at', NEW.witness_generated_at,
+      'proof_generated_at', NEW.proof_generated_at,
+      'proof', NEW.proof, 
+      'endpoint_type', NEW.endpoint_type,
+      'endpoint', NEW.endpoint,
+      'public_inputs', NEW.public_inputs,
+      'reason', NEW.reason,
+      'identifier', NEW.identifier,
+      'attempts', NEW.attempts,
+      'error_code', NEW.error_code,
+      'error_data', NEW.error_data
+    );
+
+    PERFORM pg_notify('status_update', notification_payload::text);
+  END IF;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,60 @@
+-- requests submitted together in one session share the session uuid as batch_id
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS batch_id UUID;
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS batch_index INTEGER;
+CREATE UNIQUE INDEX IF NOT EXISTS proofs_batch ON proofs (batch_id, batch_index) WHERE batch_id IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION status_update_notify() RETURNS trigger AS $$
+DECLARE
+  notification_payload JSON;
+BEGIN
+  IF (TG_OP = 'UPDATE' AND NEW.status IS DISTINCT FROM OLD.status) OR TG_OP = 'INSERT' THEN
+    -- notifications are limited to 8000 bytes, so the public signals, which repeat the
+    -- public inputs with the decoded revealed data, are left for the listener to read
+    notification_payload = json_build_object(
+      'request_id', NEW.request_id,
+      'proof_type', NEW.proof_type,
+      'status', NEW.status,
+      'created_at', NEW.created_at,
+      'circuit_name', NEW.circuit_name,
+      'onchain', NEW.onchain, 
+      'witness_generated_at', NEW.witness_generated_at,
+      'proof_generated_at', NEW.proof_generated_at,
+      'proof', NEW.proof, 
+      'endpoint_type', NEW.endpoint_type,
+      'endpoint', NEW.endpoint,
+      'public_inputs', NEW.public_inputs,
+      'reason', NEW.reason,
+      'identifier', NEW.identifier,
+      'attempts', NEW.attempts,
+      'error_code', NEW.error_code,
+      'error_data', NEW.error_data,
+      'batch_id', NEW.batch_id,
+      'batch_index', NEW.batch_index
+    );
+
+    PERFORM pg_notify('status_update', notification_payload::text);
+  END IF;
+
+  -- once the last request of a batch has finished; the lock makes sure that of two
+  -- requests finishing at the same time, the second sees the first one committed
+  IF TG_OP = 'UPDATE' AND NEW.batch_id IS NOT NULL AND NEW.status IS DISTINCT FROM OLD.status
+      AND NEW.status IN (2, 3, 4) THEN
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.batch_id::text));
+    IF NOT EXISTS (
+      SELECT 1 FROM proofs
+      WHERE batch_id = NEW.batch_id AND request_id <> NEW.request_id
+        AND (status IS NULL OR status NOT IN (2, 3, 4))
+    ) THEN
+      PERFORM pg_notify('batch_update', json_build_object(
+        'batch_id', NEW.batch_id,
+        'requests', (
+          SELECT json_agg(json_build_object('request_id', request_id, 'status', status) ORDER BY batch_index)
+          FROM proofs WHERE batch_id = NEW.batch_id
+        )
+      )::text);
+    END IF;
+  END IF;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,2 @@
+-- the server that recorded the request, which is the only one to sweep it when stuck
+ALTER TABLE proofs ADD COLUMN IF NOT EXISTS instance_id TEXT;
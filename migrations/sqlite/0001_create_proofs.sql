@@ -0,0 +1,21 @@
+-- same columns as the postgres schema; uuids, timestamps and json are stored as text and
+-- there is no status notification
+CREATE TABLE IF NOT EXISTS proofs (
+    request_id TEXT PRIMARY KEY,
+    proof_type INTEGER NOT NULL,
+    status INTEGER DEFAULT 0,
+    circuit_name TEXT NOT NULL,
+    onchain BOOLEAN NOT NULL,
+    created_at TEXT,
+    witness_generated_at TEXT,
+    proof_generated_at TEXT,
+    proof TEXT,
+    endpoint_type TEXT,
+    endpoint TEXT,
+    public_inputs TEXT,
+    reason TEXT,
+    identifier TEXT,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    error_code INTEGER,
+    error_data TEXT
+);
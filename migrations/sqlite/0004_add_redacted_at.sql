@@ -0,0 +1,3 @@
+-- set when the retention task clears the proof and request details
+ALTER TABLE proofs ADD COLUMN redacted_at TEXT;
+CREATE INDEX IF NOT EXISTS proofs_proof_type_created_at ON proofs (proof_type, created_at);
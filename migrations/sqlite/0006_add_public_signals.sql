@@ -0,0 +1,2 @@
+-- the public inputs keyed by signal name, with the decoded revealed data of disclose proofs
+ALTER TABLE proofs ADD COLUMN public_signals TEXT;
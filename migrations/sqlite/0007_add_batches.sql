@@ -0,0 +1,4 @@
+-- requests submitted together in one session share the session uuid as batch_id
+ALTER TABLE proofs ADD COLUMN batch_id TEXT;
+ALTER TABLE proofs ADD COLUMN batch_index INTEGER;
+CREATE UNIQUE INDEX IF NOT EXISTS proofs_batch ON proofs (batch_id, batch_index) WHERE batch_id IS NOT NULL;
@@ -0,0 +1,4 @@
+-- a request can wait for the proof of another request, it is blocked until then
+ALTER TABLE proofs ADD COLUMN depends_on TEXT;
+ALTER TABLE proofs ADD COLUMN unblocked_at TEXT;
+CREATE INDEX IF NOT EXISTS proofs_depends_on ON proofs (depends_on) WHERE depends_on IS NOT NULL;
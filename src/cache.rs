@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rand_core::RngCore;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::db::Proof;
+use crate::generator::Circuit;
+use crate::server::NitroRng;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Reuses generated proofs for requests with the same circuit, zkey and inputs.
+///
+/// The inputs are hashed with a salt generated in the enclave, so the stored keys cannot
+/// be matched against guessed passport data. The salt is not persisted, a proof is only
+/// reused by the enclave that generated it.
+///
+/// The database is outside the enclave, so the enclave seals every proof it generates
+/// with a MAC over the cache key and the proof, and only reuses proofs whose seal holds.
+/// A proof altered or moved to the key of other inputs is generated again instead.
+pub struct ProofCache {
+    salt: [u8; 32],
+    seal_key: [u8; 32],
+    ttl: Duration,
+    zkey_hashes: HashMap<String, Vec<u8>>,
+}
+
+impl ProofCache {
+    /// Hashes the zkey of every circuit in `circuit_zkey_map`, which takes a while for
+    /// large zkeys.
+    pub fn new(
+        fd: i32,
+        ttl: Duration,
+        circuit_zkey_map: &HashMap<String, String>,
+    ) -> std::io::Result<Self> {
+        let mut salt = [0u8; 32];
+        let mut seal_key = [0u8; 32];
+        let mut nitro_rng = NitroRng::new(fd);
+        nitro_rng.fill_bytes(&mut salt);
+        nitro_rng.fill_bytes(&mut seal_key);
+
+        let mut zkey_hashes = HashMap::new();
+        for (circuit_name, zkey_path) in circuit_zkey_map {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut std::fs::File::open(zkey_path)?, &mut hasher)?;
+            zkey_hashes.insert(circuit_name.clone(), hasher.finalize().to_vec());
+        }
+
+        Ok(Self {
+            salt,
+            seal_key,
+            ttl,
+            zkey_hashes,
+        })
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// `None` for inputs that are not valid JSON, which are never cached.
+    pub fn key(&self, circuit: &Circuit) -> Option<Vec<u8>> {
+        let zkey_hash = self.zkey_hashes.get(&circuit.name)?;
+        let inputs: serde_json::Value = serde_json::from_str(&circuit.inputs).ok()?;
+        let inputs_hash = Sha256::new()
+            .chain_update(self.salt)
+            .chain_update(serde_json::to_vec(&canonical(inputs)).ok()?)
+            .finalize();
+
+        let mut hasher = Sha256::new();
+        hasher.update((circuit.name.len() as u64).to_be_bytes());
+        hasher.update(circuit.name.as_bytes());
+        hasher.update(zkey_hash);
+        hasher.update(inputs_hash);
+        Some(hasher.finalize().to_vec())
+    }
+
+    /// The seal of a proof generated for the inputs of `cache_key`.
+    pub fn seal(
+        &self,
+        cache_key: &[u8],
+        proof: &Proof,
+        public_inputs: &[String],
+        public_signals: Option<&Value>,
+    ) -> Vec<u8> {
+        self.mac(cache_key, proof, public_inputs, public_signals)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    /// Whether `seal` was made by this enclave for this proof and `cache_key`.
+    pub fn verify(
+        &self,
+        cache_key: &[u8],
+        proof: &Proof,
+        public_inputs: &[String],
+        public_signals: Option<&Value>,
+        seal: &[u8],
+    ) -> bool {
+        self.mac(cache_key, proof, public_inputs, public_signals)
+            .verify_slice(seal)
+            .is_ok()
+    }
+
+    //every part is prefixed with its length so that none can be shifted into another
+    fn mac(
+        &self,
+        cache_key: &[u8],
+        proof: &Proof,
+        public_inputs: &[String],
+        public_signals: Option<&Value>,
+    ) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.seal_key).expect("any key length is valid");
+        let parts = [
+            cache_key.to_vec(),
+            serde_json::to_vec(proof).unwrap_or_default(),
+            serde_json::to_vec(public_inputs).unwrap_or_default(),
+            serde_json::to_vec(&public_signals).unwrap_or_default(),
+        ];
+        for part in parts {
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(&part);
+        }
+        mac
+    }
+}
+
+//sorts object keys, so the same inputs always serialize the same way whatever order they
+//were sent in and whether or not serde_json preserves it
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonical(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonical).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> ProofCache {
+        ProofCache {
+            salt: [7; 32],
+            seal_key: [8; 32],
+            ttl: Duration::from_secs(60),
+            zkey_hashes: HashMap::from([("register".to_string(), vec![1, 2, 3])]),
+        }
+    }
+
+    fn circuit(inputs: &str) -> Circuit {
+        Circuit {
+            name: "register".to_string(),
+            inputs: inputs.to_string(),
+        }
+    }
+
+    #[test]
+    fn key_ignores_key_order() {
+        let cache = cache();
+        let key = cache.key(&circuit(
+            r#"{"a":"1","b":{"c":["2",{"d":"3","e":"4"}],"f":"5"}}"#,
+        ));
+        let reordered = cache.key(&circuit(
+            r#"{"b":{"f":"5","c":["2",{"e":"4","d":"3"}]},"a":"1"}"#,
+        ));
+        assert!(key.is_some());
+        assert_eq!(key, reordered);
+    }
+
+    #[test]
+    fn key_depends_on_values() {
+        let cache = cache();
+        let key = cache.key(&circuit(r#"{"a":"1","b":["2","3"]}"#));
+        assert_ne!(key, cache.key(&circuit(r#"{"a":"1","b":["3","2"]}"#)));
+        assert_ne!(key, cache.key(&circuit(r#"{"a":"2","b":["2","3"]}"#)));
+        assert_eq!(cache.key(&circuit("not json")), None);
+    }
+
+    fn proof(pi_c: &str) -> Proof {
+        serde_json::from_value(serde_json::json!({
+            "pi_a": ["1", "2"],
+            "pi_b": [["3"], ["4"]],
+            "pi_c": [pi_c],
+            "protocol": "groth16",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn seal_covers_key_and_proof() {
+        let cache = cache();
+        let inputs = ["1".to_string()];
+        let signals = serde_json::json!({ "nullifier": "1" });
+        let seal = cache.seal(b"key", &proof("5"), &inputs, Some(&signals));
+        assert!(cache.verify(b"key", &proof("5"), &inputs, Some(&signals), &seal));
+
+        assert!(!cache.verify(b"other", &proof("5"), &inputs, Some(&signals), &seal));
+        assert!(!cache.verify(b"key", &proof("6"), &inputs, Some(&signals), &seal));
+        assert!(!cache.verify(
+            b"key",
+            &proof("5"),
+            &["2".to_string()],
+            Some(&signals),
+            &seal
+        ));
+        assert!(!cache.verify(b"key", &proof("5"), &inputs, None, &seal));
+        assert!(!cache.verify(b"key", &proof("5"), &inputs, Some(&signals), &seal[1..]));
+
+        //a restarted enclave cannot vouch for the proofs of the previous one
+        let restarted = ProofCache {
+            seal_key: [9; 32],
+            ..cache
+        };
+        assert!(!restarted.verify(b"key", &proof("5"), &inputs, Some(&signals), &seal));
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::types::chrono::Utc;
+use tokio::sync::Mutex;
+
+use super::types::{ProofRecord, Status};
+use super::{NewProof, Proof, ProofStore};
+use crate::error::Error;
+use crate::types::ProofType;
+
+/// Keeps the proofs in memory, for tests and local runs. Everything is lost on restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    proofs: Mutex<HashMap<uuid::Uuid, ProofRecord>>,
+}
+
+impl MemoryStore {
+    //updates of unknown requests are ignored, like an UPDATE that matches no row
+    async fn update(&self, uuid: uuid::Uuid, f: impl FnOnce(&mut ProofRecord)) {
+        if let Some(record) = self.proofs.lock().await.get_mut(&uuid) {
+            f(record);
+        }
+    }
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    /// Moves every timestamp of the request `by` into the past.
+    pub async fn backdate(&self, uuid: uuid::Uuid, by: Duration) {
+        self.update(uuid, |record| {
+            for at in [
+                &mut record.created_at,
+                &mut record.witness_generated_at,
+                &mut record.proof_generated_at,
+                &mut record.unblocked_at,
+            ] {
+                *at = at.map(|at| at - by);
+            }
+        })
+        .await;
+    }
+}
+
+#[async_trait]
+impl ProofStore for MemoryStore {
+    async fn create(&self, proof: &NewProof<'_>) -> Result<(), Error> {
+        let mut proofs = self.proofs.lock().await;
+        if proofs.contains_key(&proof.uuid) {
+            return Err(Error::DuplicateUuid);
+        }
+
+        proofs.insert(proof.uuid, new_record(proof, None));
+        Ok(())
+    }
+
+    async fn create_batch(
+        &self,
+        batch_id: uuid::Uuid,
+        new_proofs: &[NewProof<'_>],
+    ) -> Result<(), Error> {
+        let mut proofs = self.proofs.lock().await;
+        if new_proofs
+            .iter()
+            .any(|proof| proofs.contains_key(&proof.uuid))
+            || proofs
+                .values()
+                .any(|record| record.batch_id == Some(batch_id))
+        {
+            return Err(Error::DuplicateUuid);
+        }
+
+        for (batch_index, proof) in new_proofs.iter().enumerate() {
+            proofs.insert(
+                proof.uuid,
+                new_record(proof, Some((batch_id, batch_index as i32))),
+            );
+        }
+        Ok(())
+    }
+
+    async fn unblock_proof(&self, uuid: uuid::Uuid) -> Result<bool, Error> {
+        let mut unblocked = false;
+        self.update(uuid, |record| {
+            if record.status == Status::Blocked {
+                record.status = Status::Pending;
+                record.unblocked_at = Some(Utc::now());
+                unblocked = true;
+            }
+        })
+        .await;
+        Ok(unblocked)
+    }
+
+    async fn set_witness_generated(&self, uuid: uuid::Uuid) -> Result<(), Error> {
+        self.update(uuid, |record| {
+            if !record.status.is_in_progress() {
+                return;
+            }
+            record.status = Status::WitnessGenerated;
+            record.witness_generated_at = Some(Utc::now());
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn update_proof(
+        &self,
+        uuid: uuid::Uuid,
+        proof: &Proof,
+        public_inputs: &[String],
+        public_signals: Option<&serde_json::Value>,
+        cache_seal: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        self.update(uuid, |record| {
+            if !record.status.is_in_progress() {
+                return;
+            }
+            record.status = Status::ProofGenerated;
+            record.proof_generated_at = Some(Utc::now());
+            record.proof = Some(proof.clone());
+            record.public_inputs = Some(public_inputs.to_vec());
+            record.public_signals = public_signals.cloned();
+            record.cache_seal = cache_seal.map(<[u8]>::to_vec);
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn fail_proof(&self, uuid: uuid::Uuid, error: &Error) -> Result<(), Error> {
+        self.update(uuid, |record| {
+            if !record.status.is_in_progress() {
+                return;
+            }
+            record.status = Status::Failed;
+            record.reason = Some(error.to_string());
+            record.error_code = Some(error.code());
+            record.error_data = error.data();
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn increment_attempts(&self, uuid: uuid::Uuid) -> Result<(), Error> {
+        self.update(uuid, |record| record.attempts += 1).await;
+        Ok(())
+    }
+
+    async fn cancel_proof(&self, uuid: uuid::Uuid) -> Result<(), Error> {
+        self.update(uuid, |record| {
+            if record.status.is_in_progress() {
+                record.status = Status::Cancelled;
+            }
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn fail_stuck_proofs(
+        &self,
+        older_than: Duration,
+        proof_types: &[ProofType],
+        active: &[uuid::Uuid],
+    ) -> Result<u64, Error> {
+        let error = Error::Abandoned;
+        let cutoff = Utc::now() - older_than;
+        let mut failed = 0;
+        for record in self.proofs.lock().await.values_mut() {
+            //blocked requests are failed by fail_blocked_proofs
+            if matches!(record.status, Status::Pending | Status::WitnessGenerated)
+                && record
+                    .unblocked_at
+                    .or(record.created_at)
+                    .is_some_and(|since| since < cutoff)
+                && proof_types.contains(&record.proof_type)
+                && !active.contains(&record.request_id)
+            {
+                record.status = Status::Failed;
+                record.reason = Some(error.to_string());
+                record.error_code = Some(error.code());
+                failed += 1;
+            }
+        }
+        Ok(failed)
+    }
+
+    async fn fail_blocked_proofs(&self, older_than: Duration) -> Result<Vec<uuid::Uuid>, Error> {
+        let error = Error::Abandoned;
+        let cutoff = Utc::now() - older_than;
+        let mut failed = Vec::new();
+        for record in self.proofs.lock().await.values_mut() {
+            if record.status == Status::Blocked
+                && record
+                    .created_at
+                    .is_some_and(|created_at| created_at <= cutoff)
+            {
+                record.status = Status::Failed;
+                record.reason = Some(error.to_string());
+                record.error_code = Some(error.code());
+                failed.push(record.request_id);
+            }
+        }
+        Ok(failed)
+    }
+
+    async fn find_cached(
+        &self,
+        cache_key: &[u8],
+        max_age: Duration,
+    ) -> Result<Option<ProofRecord>, Error> {
+        let cutoff = Utc::now() - max_age;
+        Ok(self
+            .proofs
+            .lock()
+            .await
+            .values()
+            .filter(|record| {
+                record.cache_key.as_deref() == Some(cache_key)
+                    && record.status == Status::ProofGenerated
+                    && record.proof.is_some()
+                    && record
+                        .proof_generated_at
+                        .is_some_and(|generated_at| generated_at > cutoff)
+            })
+            .max_by_key(|record| record.proof_generated_at)
+            .cloned())
+    }
+
+    async fn redact_proofs(
+        &self,
+        proof_type: ProofType,
+        older_than: Duration,
+    ) -> Result<u64, Error> {
+        let now = Utc::now();
+        let cutoff = now - older_than;
+        let mut redacted = 0;
+        for record in self.proofs.lock().await.values_mut() {
+            if record.proof_type == proof_type
+                && !record.status.is_in_progress()
+                && record.redacted_at.is_none()
+                && record
+                    .proof_generated_at
+                    .or(record.created_at)
+                    .is_some_and(|finished_at| finished_at < cutoff)
+            {
+                record.proof = None;
+                record.public_inputs = None;
+                record.public_signals = None;
+                record.endpoint = None;
+                record.identifier = None;
+                record.identifier_key_hash = None;
+                record.reason = None;
+                record.error_data = None;
+                record.cache_key = None;
+                record.cache_seal = None;
+                record.redacted_at = Some(now);
+                redacted += 1;
+            }
+        }
+        Ok(redacted)
+    }
+
+    async fn delete_proofs(
+        &self,
+        proof_type: ProofType,
+        older_than: Duration,
+    ) -> Result<u64, Error> {
+        let cutoff = Utc::now() - older_than;
+        let mut proofs = self.proofs.lock().await;
+        let before = proofs.len();
+        proofs.retain(|_, record| {
+            record.proof_type != proof_type
+                || record.status.is_in_progress()
+                || record
+                    .created_at
+                    .is_none_or(|created_at| created_at >= cutoff)
+        });
+        Ok((before - proofs.len()) as u64)
+    }
+
+    async fn get(&self, uuid: uuid::Uuid) -> Result<Option<ProofRecord>, Error> {
+        Ok(self.proofs.lock().await.get(&uuid).cloned())
+    }
+
+    async fn find_by_identifier(
+        &self,
+        identifier: &str,
+        identifier_key_hash: &[u8],
+        before: Option<uuid::Uuid>,
+        limit: u32,
+    ) -> Result<Vec<ProofRecord>, Error> {
+        let proofs = self.proofs.lock().await;
+        let position = |record: &ProofRecord| (record.created_at, record.request_id);
+        //a request that is gone has nothing after it
+        let before = match before {
+            Some(before) => match proofs.get(&before) {
+                Some(record) => Some(position(record)),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+        let mut records: Vec<ProofRecord> = proofs
+            .values()
+            .filter(|record| {
+                record.identifier.as_deref() == Some(identifier)
+                    && record.identifier_key_hash.as_deref() == Some(identifier_key_hash)
+                    && before.is_none_or(|before| position(record) < before)
+            })
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(position(record)));
+        records.truncate(limit as usize);
+        Ok(records)
+    }
+
+    async fn find_batch(&self, batch_id: uuid::Uuid) -> Result<Vec<ProofRecord>, Error> {
+        let mut records: Vec<ProofRecord> = self
+            .proofs
+            .lock()
+            .await
+            .values()
+            .filter(|record| record.batch_id == Some(batch_id))
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| record.batch_index);
+        Ok(records)
+    }
+
+    async fn list(&self, status: Option<Status>, limit: u32) -> Result<Vec<ProofRecord>, Error> {
+        let mut records: Vec<ProofRecord> = self
+            .proofs
+            .lock()
+            .await
+            .values()
+            .filter(|record| status.is_none_or(|status| record.status == status))
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        records.truncate(limit as usize);
+        Ok(records)
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<i64, Error> {
+        Ok(0)
+    }
+
+    async fn check_schema(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn new_record(proof: &NewProof<'_>, batch: Option<(uuid::Uuid, i32)>) -> ProofRecord {
+    ProofRecord {
+        request_id: proof.uuid,
+        proof_type: proof.proof_type,
+        status: proof.status(),
+        circuit_name: proof.circuit_name.to_string(),
+        onchain: proof.onchain,
+        created_at: Some(Utc::now()),
+        witness_generated_at: None,
+        proof_generated_at: proof.cached.as_ref().map(|_| Utc::now()),
+        proof: proof.cached.as_ref().map(|cached| cached.proof.clone()),
+        endpoint_type: proof.endpoint_type,
+        endpoint: proof.endpoint.map(str::to_string),
+        public_inputs: proof
+            .cached
+            .as_ref()
+            .map(|cached| cached.public_inputs.to_vec()),
+        public_signals: proof
+            .cached
+            .as_ref()
+            .and_then(|cached| cached.public_signals.cloned()),
+        reason: None,
+        identifier: proof.identifier.map(str::to_string),
+        identifier_key_hash: proof.identifier_key_hash.map(<[u8]>::to_vec),
+        attempts: 1,
+        error_code: None,
+        error_data: None,
+        payload_digest: Some(proof.payload_digest.to_vec()),
+        cache_key: proof.cache_key.map(<[u8]>::to_vec),
+        cache_seal: None,
+        redacted_at: None,
+        batch_id: batch.map(|(batch_id, _)| batch_id),
+        batch_index: batch.map(|(_, batch_index)| batch_index),
+        depends_on: proof.depends_on,
+        unblocked_at: None,
+    }
+}
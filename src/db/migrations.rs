@@ -0,0 +1,73 @@
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+
+use crate::error::Error;
+
+pub static POSTGRES: Migrator = sqlx::migrate!("migrations/postgres");
+pub static SQLITE: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// The schema version this server was built for.
+pub fn version(migrator: &Migrator) -> i64 {
+    migrator.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/// Applies the pending migrations, failing if the database has migrations this server
+/// does not know about or that were changed after being applied.
+pub async fn run<DB>(migrator: &Migrator, db: &sqlx::Pool<DB>) -> Result<(), Error>
+where
+    DB: sqlx::Database,
+    DB::Connection: Migrate,
+{
+    migrator.run(db).await.map_err(|e| match e {
+        MigrateError::VersionMissing(version) => newer_schema(migrator, version),
+        e => incompatible(e.to_string()),
+    })
+}
+
+/// Checks that every migration has been applied, given the `(version, success, checksum)`
+/// rows of `_sqlx_migrations`.
+pub fn check(migrator: &Migrator, applied: &[(i64, bool, Vec<u8>)]) -> Result<(), Error> {
+    for (version, success, checksum) in applied {
+        match migrator.iter().find(|m| m.version == *version) {
+            None => return Err(newer_schema(migrator, *version)),
+            Some(_) if !success => {
+                return Err(incompatible(format!(
+                    "migration {} is partially applied",
+                    version
+                )))
+            }
+            Some(migration) if *migration.checksum != checksum[..] => {
+                return Err(incompatible(format!(
+                    "migration {} was changed after it was applied",
+                    version
+                )))
+            }
+            Some(_) => (),
+        }
+    }
+
+    match migrator
+        .iter()
+        .find(|m| !applied.iter().any(|(version, _, _)| *version == m.version))
+    {
+        Some(missing) => Err(incompatible(format!(
+            "migration {} ({}) has not been applied, run `tee-server migrate`",
+            missing.version, missing.description
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn newer_schema(migrator: &Migrator, version: i64) -> Error {
+    incompatible(format!(
+        "the database has migration {}, newer than the schema version {} of this server",
+        version,
+        self::version(migrator)
+    ))
+}
+
+fn incompatible(message: String) -> Error {
+    Error::Database {
+        message: format!("incompatible database schema: {}", message),
+        retryable: false,
+    }
+}
@@ -1,73 +1,309 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use sqlx::types::chrono::Utc;
 
 use crate::{
+    cache::ProofCache,
+    error::Error,
+    generator::Circuit,
+    signals::PublicSignals,
+    transport::{self, Address},
     types::{EndpointType, ProofType},
-    utils::get_tmp_folder_path,
+    utils::{get_scratch_dir, get_tmp_folder_path},
 };
+pub mod memory;
+pub mod migrations;
+pub mod postgres;
+pub mod sqlite;
+#[cfg(test)]
+mod tests;
+pub mod tls;
 pub mod types;
 
+use memory::MemoryStore;
+use postgres::PgStore;
+use sqlite::SqliteStore;
+use tls::DatabaseTls;
+use types::{ProofRecord, Status};
+
+use std::os::unix::fs::DirBuilderExt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
 type PublicInputs = Vec<String>;
 
-pub async fn create_proof_status(
-    uuid: uuid::Uuid,
-    proof_type: &ProofType,
-    circuit_name: &str,
-    on_chain: bool,
-    db: &sqlx::Pool<sqlx::Postgres>,
-    endpoint_type: Option<&EndpointType>,
-    endpoint: Option<&String>,
-) -> Result<(), String> {
-    let proof_type_id: i32 = proof_type.into();
-    let now = Utc::now();
-
-    let status: i32 = types::Status::Pending.into();
-
-    let _ = sqlx::query(
-        "INSERT INTO proofs (proof_type, request_id, status, created_at, circuit_name, onchain, endpoint_type, endpoint) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-    )
-    .bind(proof_type_id)
-    .bind(sqlx::types::Uuid::from(uuid))
-    .bind(status)
-    .bind(now)
-    .bind(circuit_name)
-    .bind(on_chain)
-    .bind(endpoint_type.map(|e| serde_plain::to_string(e).unwrap()))
-    .bind(endpoint)
-    .execute(db)
-    .await.map_err(|e| {
-        dbg!(e);
-        return "Could not create the record";
-    })?;
-
-    Ok(())
+/// Builds the connection options from `url`, optionally connecting through `address`
+/// and over `tls`.
+///
+/// Either goes through an in-process bridge on a unix socket in the scratch folder. With
+/// `tls` the bridge does the handshake itself and checks the server as the host in the
+/// url, and sqlx talks plain text to the bridge.
+pub fn connect_options(
+    url: &str,
+    address: Option<&Address>,
+    tls: Option<DatabaseTls>,
+) -> Result<PgConnectOptions, Error> {
+    let mut options = PgConnectOptions::from_str(url)?;
+    if address.is_none() && tls.is_none() {
+        return Ok(options);
+    }
+
+    let use_tls = tls.is_some();
+    let host = options.get_host().to_string();
+    let target = address
+        .cloned()
+        .unwrap_or_else(|| Address::Tcp(format!("{}:{}", host, options.get_port())));
+
+    let socket_dir = get_scratch_dir().join("db");
+    let socket_path = socket_dir.join(format!(".s.PGSQL.{}", options.get_port()));
+    let bridged = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&socket_dir)
+        .and_then(|_| match tls {
+            Some(tls) => {
+                let target = target.clone();
+                transport::bridge(&socket_path, move || {
+                    let (tls, target, host) = (tls.clone(), target.clone(), host.clone());
+                    async move { tls.connect(target.connect().await?, &host).await }
+                })
+            }
+            None => {
+                let target = target.clone();
+                transport::bridge(&socket_path, move || {
+                    let target = target.clone();
+                    async move { target.connect().await }
+                })
+            }
+        });
+    if let Err(e) = bridged {
+        return Err(Error::Database {
+            message: format!("could not bridge to database address {}: {}", target, e),
+            retryable: false,
+        });
+    }
+
+    options = options.socket(socket_dir);
+    if use_tls {
+        options = options.ssl_mode(PgSslMode::Disable);
+    }
+    Ok(options)
 }
 
-pub async fn set_witness_generated(
-    uuid: uuid::Uuid,
-    db: &sqlx::Pool<sqlx::Postgres>,
-) -> Result<(), sqlx::Error> {
-    let status: i32 = types::Status::WitnessGenerated.into();
-    let now = Utc::now();
-
-    match sqlx::query(&format!(
-        "UPDATE proofs SET status = $1, witness_generated_at = $2 WHERE request_id = $3",
-    ))
-    .bind(status)
-    .bind(now)
-    .bind(sqlx::types::Uuid::from(uuid))
-    .execute(db)
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            dbg!(&e);
-            return Err(e);
+/// A request accepted by `submit_request`, as it is first recorded.
+pub struct NewProof<'a> {
+    pub uuid: uuid::Uuid,
+    pub proof_type: ProofType,
+    pub circuit_name: &'a str,
+    pub onchain: bool,
+    pub endpoint_type: Option<EndpointType>,
+    pub endpoint: Option<&'a str>,
+    /// Identifies the submitted payload so that retries can be recognised.
+    pub payload_digest: &'a [u8],
+    /// Scope or user id of the verifying app, for disclose requests.
+    pub identifier: Option<&'a str>,
+    /// SHA-256 of the key the requests of `identifier` are listed with.
+    pub identifier_key_hash: Option<&'a [u8]>,
+    /// Set when the proof may be reused for later requests with the same inputs.
+    pub cache_key: Option<&'a [u8]>,
+    /// The request whose proof this one waits for.
+    pub depends_on: Option<uuid::Uuid>,
+    /// Recorded as `Blocked` instead of `Pending`, the request it depends on has not
+    /// generated its proof yet.
+    pub blocked: bool,
+    /// Recorded as `ProofGenerated` with the proof of an earlier request with the same
+    /// inputs.
+    pub cached: Option<CachedProof<'a>>,
+}
+
+impl NewProof<'_> {
+    /// The status the request is first recorded with.
+    pub fn status(&self) -> Status {
+        if self.cached.is_some() {
+            Status::ProofGenerated
+        } else if self.blocked {
+            Status::Blocked
+        } else {
+            Status::Pending
         }
     }
 }
 
-pub async fn update_proof(uuid: uuid::Uuid, db: &sqlx::Pool<sqlx::Postgres>) -> Result<(), String> {
+/// A proof served from the cache, with its public inputs and named public signals.
+pub struct CachedProof<'a> {
+    pub proof: &'a Proof,
+    pub public_inputs: &'a [String],
+    pub public_signals: Option<&'a serde_json::Value>,
+}
+
+/// Where proof requests and their results are kept.
+#[async_trait]
+pub trait ProofStore: Send + Sync {
+    /// Records a new pending request, failing with `DuplicateUuid` if it already exists.
+    async fn create(&self, proof: &NewProof<'_>) -> Result<(), Error>;
+
+    /// Records the requests of a batch, in order, all or none. Fails with `DuplicateUuid`
+    /// if the batch already exists.
+    async fn create_batch(
+        &self,
+        batch_id: uuid::Uuid,
+        proofs: &[NewProof<'_>],
+    ) -> Result<(), Error>;
+
+    /// Moves a blocked request to pending. Returns false if it is no longer blocked, e.g.
+    /// because it was cancelled.
+    async fn unblock_proof(&self, uuid: uuid::Uuid) -> Result<bool, Error>;
+
+    /// The updates of a request in the pipeline leave it alone once it has finished, so
+    /// that a cancelled request stays cancelled.
+    async fn set_witness_generated(&self, uuid: uuid::Uuid) -> Result<(), Error>;
+
+    /// Stores the generated proof, with the public inputs keyed by signal name if the
+    /// names of the circuit are known, and the seal of the proof cache if it may be
+    /// reused.
+    async fn update_proof(
+        &self,
+        uuid: uuid::Uuid,
+        proof: &Proof,
+        public_inputs: &[String],
+        public_signals: Option<&serde_json::Value>,
+        cache_seal: Option<&[u8]>,
+    ) -> Result<(), Error>;
+
+    async fn fail_proof(&self, uuid: uuid::Uuid, error: &Error) -> Result<(), Error>;
+
+    async fn increment_attempts(&self, uuid: uuid::Uuid) -> Result<(), Error>;
+
+    /// Cancels the request unless it has already finished.
+    async fn cancel_proof(&self, uuid: uuid::Uuid) -> Result<(), Error>;
+
+    /// Fails the `proof_types` requests of this server that have been pending for longer
+    /// than `older_than`, skipping the requests it is still working on. Requests recorded
+    /// by other servers sharing the store are theirs to sweep, only requests recorded
+    /// before servers had an id are swept by any of them. Blocked requests are left to
+    /// `fail_blocked_proofs` and count from when they were unblocked. Returns the number
+    /// of failed requests.
+    async fn fail_stuck_proofs(
+        &self,
+        older_than: Duration,
+        proof_types: &[ProofType],
+        active: &[uuid::Uuid],
+    ) -> Result<u64, Error>;
+
+    /// Fails the blocked requests of this server created more than `older_than` ago,
+    /// however far the request they depend on has got, and returns them. On startup
+    /// every blocked request is failed, the holds that would have unblocked them were
+    /// lost with the previous run.
+    async fn fail_blocked_proofs(&self, older_than: Duration) -> Result<Vec<uuid::Uuid>, Error>;
+
+    /// The most recent proof generated for `cache_key` within `max_age`, if any. Its seal
+    /// is left to the caller to check.
+    async fn find_cached(
+        &self,
+        cache_key: &[u8],
+        max_age: Duration,
+    ) -> Result<Option<ProofRecord>, Error>;
+
+    /// Clears the proof, public inputs and request details of the finished `proof_type`
+    /// requests that finished more than `older_than` ago. Returns the number of redacted
+    /// requests.
+    async fn redact_proofs(
+        &self,
+        proof_type: ProofType,
+        older_than: Duration,
+    ) -> Result<u64, Error>;
+
+    /// Deletes the finished `proof_type` requests created more than `older_than` ago.
+    /// Returns the number of deleted requests.
+    async fn delete_proofs(
+        &self,
+        proof_type: ProofType,
+        older_than: Duration,
+    ) -> Result<u64, Error>;
+
+    async fn get(&self, uuid: uuid::Uuid) -> Result<Option<ProofRecord>, Error>;
+
+    /// The requests of a batch in the order they were submitted, empty if there is no
+    /// such batch.
+    async fn find_batch(&self, batch_id: uuid::Uuid) -> Result<Vec<ProofRecord>, Error>;
+
+    /// The most recent requests submitted with `identifier` and the key hashed to
+    /// `identifier_key_hash` first. With `before`, only the requests that come after that
+    /// one, none if it is gone.
+    async fn find_by_identifier(
+        &self,
+        identifier: &str,
+        identifier_key_hash: &[u8],
+        before: Option<uuid::Uuid>,
+        limit: u32,
+    ) -> Result<Vec<ProofRecord>, Error>;
+
+    /// The most recent requests first, optionally only those with `status`.
+    #[allow(dead_code)]
+    async fn list(&self, status: Option<Status>, limit: u32) -> Result<Vec<ProofRecord>, Error>;
+
+    /// Checks that the store can be reached.
+    async fn ping(&self) -> Result<(), Error>;
+
+    /// Applies the pending migrations and returns the schema version.
+    async fn migrate(&self) -> Result<i64, Error>;
+
+    /// Checks that every migration has been applied, without changing the store.
+    async fn check_schema(&self) -> Result<(), Error>;
+}
+
+/// Whether `url` opens a PostgreSQL store rather than a `memory:` or `sqlite:` one.
+pub fn is_postgres(url: &str) -> bool {
+    !matches!(scheme(url), Some("memory" | "sqlite"))
+}
+
+fn scheme(url: &str) -> Option<&str> {
+    url.split_once(':').map(|(scheme, _)| scheme)
+}
+
+/// Opens the store for `url`: `memory:`, a `sqlite:` URL or a PostgreSQL URL. The
+/// `address` and `tls` settings only apply to PostgreSQL. Requests are recorded as
+/// owned by `instance_id`, except in memory where no other server can see them.
+pub async fn connect(
+    url: &str,
+    address: Option<&Address>,
+    tls: Option<DatabaseTls>,
+    max_connections: u32,
+    instance_id: &str,
+) -> Result<Arc<dyn ProofStore>, Error> {
+    match scheme(url) {
+        Some("memory") => Ok(Arc::new(MemoryStore::default())),
+        Some("sqlite") => {
+            let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+            let pool = SqlitePoolOptions::new()
+                .max_connections(max_connections)
+                .connect_with(options)
+                .await?;
+            Ok(Arc::new(SqliteStore::new(pool, instance_id.to_string())))
+        }
+        _ => {
+            let options = connect_options(url, address, tls)?;
+            let pool = PgPoolOptions::new()
+                .max_connections(max_connections)
+                .connect_with(options)
+                .await?;
+            Ok(Arc::new(PgStore::new(pool, instance_id.to_string())))
+        }
+    }
+}
+
+/// Stores the proof and public inputs written by the prover to the tmp folder, sealed for
+/// the `cache` if there is one.
+pub async fn save_proof(
+    uuid: uuid::Uuid,
+    circuit_name: &str,
+    signals: &PublicSignals,
+    cache: Option<&ProofCache>,
+    db: &dyn ProofStore,
+) -> Result<(), Error> {
     let proof_file_path =
         std::path::Path::new(&get_tmp_folder_path(&uuid.to_string())).join("proof.json");
     let public_inputs_file_path =
@@ -77,22 +313,22 @@ pub async fn update_proof(uuid: uuid::Uuid, db: &sqlx::Pool<sqlx::Postgres>) ->
     let proof_string = match std::fs::read_to_string(&proof_file_path) {
         Ok(proof_string) => proof_string,
         Err(e) => {
-            dbg!(&e);
-            return Err(format!(
+            tracing::error!(error = %e, "could not read proof");
+            return Err(Error::InvalidProofOutput(format!(
                 "Could not read proof from path: {}",
                 proof_file_path.display(),
-            ));
+            )));
         }
     };
 
     let public_inputs_string = match std::fs::read_to_string(&public_inputs_file_path) {
         Ok(public_inputs_string) => public_inputs_string,
         Err(e) => {
-            dbg!(&e);
-            return Err(format!(
+            tracing::error!(error = %e, "could not read public inputs");
+            return Err(Error::InvalidProofOutput(format!(
                 "Could not read public inputs from path: {}",
                 public_inputs_file_path.display(),
-            ));
+            )));
         }
     };
 
@@ -101,7 +337,10 @@ pub async fn update_proof(uuid: uuid::Uuid, db: &sqlx::Pool<sqlx::Postgres>) ->
     let proof = match Proof::deserialize(&mut proof_reader) {
         Ok(proof) => proof,
         Err(e) => {
-            return Err(format!("Could not deserialize proof: {}", e));
+            return Err(Error::InvalidProofOutput(format!(
+                "Could not deserialize proof: {}",
+                e
+            )));
         }
     };
 
@@ -110,54 +349,38 @@ pub async fn update_proof(uuid: uuid::Uuid, db: &sqlx::Pool<sqlx::Postgres>) ->
     let public_inputs = match PublicInputs::deserialize(&mut public_inputs_reader) {
         Ok(public_inputs) => public_inputs,
         Err(e) => {
-            return Err(format!("Could not deserialize public inputs: {}", e));
+            return Err(Error::InvalidProofOutput(format!(
+                "Could not deserialize public inputs: {}",
+                e
+            )));
         }
     };
 
-    let status: i32 = types::Status::ProofGenererated.into();
-
-    let now = Utc::now();
-    match sqlx::query(
-        "UPDATE proofs SET proof = $1, status = $2, proof_generated_at = $3, public_inputs = $4  WHERE request_id = $5",
+    let public_signals = signals.name(circuit_name, &public_inputs);
+    //the key is derived again from the inputs the proof was generated for, which never
+    //left the enclave
+    let cache_seal = cache.and_then(|cache| {
+        let inputs_file_path =
+            std::path::Path::new(&get_tmp_folder_path(&uuid.to_string())).join("input.json");
+        let circuit = Circuit {
+            name: circuit_name.to_string(),
+            inputs: std::fs::read_to_string(inputs_file_path).ok()?,
+        };
+        let cache_key = cache.key(&circuit)?;
+        Some(cache.seal(&cache_key, &proof, &public_inputs, public_signals.as_ref()))
+    });
+    db.update_proof(
+        uuid,
+        &proof,
+        &public_inputs,
+        public_signals.as_ref(),
+        cache_seal.as_deref(),
     )
-    .bind(sqlx::types::Json(proof))
-    .bind(status)
-    .bind(now)
-    .bind(public_inputs)
-    .bind(sqlx::types::uuid::Uuid::from(uuid))
-    .execute(db)
     .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            return Err(format!("Could not update proof: {}", e));
-        }
-    }
-}
-
-pub async fn fail_proof(
-    uuid: uuid::Uuid,
-    db: &sqlx::Pool<sqlx::Postgres>,
-    reason: String,
-) -> Result<(), sqlx::Error> {
-    let status: i32 = types::Status::Failed.into();
-    match sqlx::query("UPDATE proofs SET status = $1, reason = $2 WHERE request_id = $3")
-        .bind(status)
-        .bind(reason)
-        .bind(sqlx::types::Uuid::from(uuid))
-        .execute(db)
-        .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            dbg!(&e);
-            return Err(e);
-        }
-    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Proof {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Proof {
     pi_a: Vec<String>,
     pi_b: Vec<Vec<String>>,
     pi_c: Vec<String>,
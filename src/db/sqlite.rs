@@ -0,0 +1,567 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::sqlite::SqliteRow;
+use sqlx::types::chrono::Utc;
+use sqlx::types::Json;
+use sqlx::{Pool, Row, Sqlite};
+
+use super::types::{ProofRecord, Status};
+use super::{migrations, NewProof, Proof, ProofStore};
+use crate::error::Error;
+use crate::types::ProofType;
+
+const COLUMNS: &str = "request_id, proof_type, status, circuit_name, onchain, created_at, witness_generated_at, proof_generated_at, proof, endpoint_type, endpoint, public_inputs, public_signals, reason, identifier, identifier_key_hash, attempts, error_code, error_data, payload_digest, cache_key, cache_seal, redacted_at, batch_id, batch_index, depends_on, unblocked_at";
+
+/// Keeps the proofs in a SQLite database, for local runs without a PostgreSQL server.
+/// Uuids are stored as text and timestamps are compared with `julianday`.
+pub struct SqliteStore {
+    db: Pool<Sqlite>,
+    instance_id: String,
+}
+
+impl SqliteStore {
+    /// `instance_id` is recorded with every request, so that the sweep leaves the
+    /// requests of other servers sharing the database alone.
+    pub fn new(db: Pool<Sqlite>, instance_id: String) -> Self {
+        Self { db, instance_id }
+    }
+}
+
+#[cfg(test)]
+impl SqliteStore {
+    /// Moves every timestamp of the request `by` into the past.
+    pub async fn backdate(&self, uuid: uuid::Uuid, by: Duration) {
+        let record = self.get(uuid).await.unwrap().unwrap();
+        let back = |at: Option<sqlx::types::chrono::DateTime<Utc>>| at.map(|at| at - by);
+        sqlx::query("UPDATE proofs SET created_at = $1, witness_generated_at = $2, proof_generated_at = $3, unblocked_at = $4 WHERE request_id = $5")
+            .bind(back(record.created_at))
+            .bind(back(record.witness_generated_at))
+            .bind(back(record.proof_generated_at))
+            .bind(back(record.unblocked_at))
+            .bind(uuid.to_string())
+            .execute(&self.db)
+            .await
+            .unwrap();
+    }
+}
+
+#[async_trait]
+impl ProofStore for SqliteStore {
+    async fn create(&self, proof: &NewProof<'_>) -> Result<(), Error> {
+        insert(&self.db, &self.instance_id, proof, None).await
+    }
+
+    async fn create_batch(
+        &self,
+        batch_id: uuid::Uuid,
+        proofs: &[NewProof<'_>],
+    ) -> Result<(), Error> {
+        let mut tx = self.db.begin().await?;
+        for (batch_index, proof) in proofs.iter().enumerate() {
+            insert(
+                &mut *tx,
+                &self.instance_id,
+                proof,
+                Some((batch_id, batch_index as i32)),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn unblock_proof(&self, uuid: uuid::Uuid) -> Result<bool, Error> {
+        match sqlx::query("UPDATE proofs SET status = $1, unblocked_at = $2 WHERE request_id = $3 AND status = $4")
+            .bind(Status::Pending)
+            .bind(Utc::now())
+            .bind(uuid.to_string())
+            .bind(Status::Blocked)
+            .execute(&self.db)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(e) => {
+                tracing::error!(error = %e, "could not unblock proof");
+                metrics::counter!("db_errors_total", "query" => "unblock_proof").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn set_witness_generated(&self, uuid: uuid::Uuid) -> Result<(), Error> {
+        let now = Utc::now();
+
+        match sqlx::query(
+            "UPDATE proofs SET status = $1, witness_generated_at = $2 WHERE request_id = $3 AND status IN ($4, $5, $6)",
+        )
+        .bind(Status::WitnessGenerated)
+        .bind(now)
+        .bind(uuid.to_string())
+        .bind(Status::Pending)
+        .bind(Status::WitnessGenerated)
+        .bind(Status::Blocked)
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not set witness generated");
+                metrics::counter!("db_errors_total", "query" => "set_witness_generated")
+                    .increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn update_proof(
+        &self,
+        uuid: uuid::Uuid,
+        proof: &Proof,
+        public_inputs: &[String],
+        public_signals: Option<&serde_json::Value>,
+        cache_seal: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let now = Utc::now();
+        match sqlx::query(
+            "UPDATE proofs SET proof = $1, status = $2, proof_generated_at = $3, public_inputs = $4, public_signals = $5, cache_seal = $6 WHERE request_id = $7 AND status IN ($8, $9, $10)",
+        )
+        .bind(Json(proof))
+        .bind(Status::ProofGenerated)
+        .bind(now)
+        .bind(Json(public_inputs))
+        .bind(public_signals.map(Json))
+        .bind(cache_seal)
+        .bind(uuid.to_string())
+        .bind(Status::Pending)
+        .bind(Status::WitnessGenerated)
+        .bind(Status::Blocked)
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not update proof");
+                metrics::counter!("db_errors_total", "query" => "update_proof").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn fail_proof(&self, uuid: uuid::Uuid, error: &Error) -> Result<(), Error> {
+        match sqlx::query(
+            "UPDATE proofs SET status = $1, reason = $2, error_code = $3, error_data = $4 WHERE request_id = $5 AND status IN ($6, $7, $8)",
+        )
+        .bind(Status::Failed)
+        .bind(error.to_string())
+        .bind(error.code())
+        .bind(error.data().map(Json))
+        .bind(uuid.to_string())
+        .bind(Status::Pending)
+        .bind(Status::WitnessGenerated)
+        .bind(Status::Blocked)
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not fail proof");
+                metrics::counter!("db_errors_total", "query" => "fail_proof").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn increment_attempts(&self, uuid: uuid::Uuid) -> Result<(), Error> {
+        match sqlx::query("UPDATE proofs SET attempts = attempts + 1 WHERE request_id = $1")
+            .bind(uuid.to_string())
+            .execute(&self.db)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not increment attempts");
+                metrics::counter!("db_errors_total", "query" => "increment_attempts").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn cancel_proof(&self, uuid: uuid::Uuid) -> Result<(), Error> {
+        match sqlx::query(
+            "UPDATE proofs SET status = $1 WHERE request_id = $2 AND status IN ($3, $4, $5)",
+        )
+        .bind(Status::Cancelled)
+        .bind(uuid.to_string())
+        .bind(Status::Pending)
+        .bind(Status::WitnessGenerated)
+        .bind(Status::Blocked)
+        .execute(&self.db)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not cancel proof");
+                metrics::counter!("db_errors_total", "query" => "cancel_proof").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn fail_stuck_proofs(
+        &self,
+        older_than: Duration,
+        proof_types: &[ProofType],
+        active: &[uuid::Uuid],
+    ) -> Result<u64, Error> {
+        let error = Error::Abandoned;
+        let cutoff = Utc::now() - older_than;
+        let active: Vec<String> = active.iter().map(uuid::Uuid::to_string).collect();
+        match sqlx::query(
+            "UPDATE proofs SET status = $1, reason = $2, error_code = $3 WHERE status IN ($4, $5) AND julianday(COALESCE(unblocked_at, created_at)) < julianday($6) AND proof_type IN (SELECT value FROM json_each($7)) AND request_id NOT IN (SELECT value FROM json_each($8)) AND (instance_id IS NULL OR instance_id = $9)",
+        )
+        .bind(Status::Failed)
+        .bind(error.to_string())
+        .bind(error.code())
+        .bind(Status::Pending)
+        .bind(Status::WitnessGenerated)
+        .bind(cutoff)
+        .bind(Json(proof_types.iter().map(|t| *t as i16).collect::<Vec<i16>>()))
+        .bind(Json(active))
+        .bind(&self.instance_id)
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not fail stuck proofs");
+                metrics::counter!("db_errors_total", "query" => "fail_stuck_proofs").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn fail_blocked_proofs(&self, older_than: Duration) -> Result<Vec<uuid::Uuid>, Error> {
+        let error = Error::Abandoned;
+        let cutoff = Utc::now() - older_than;
+        let failed: Result<Vec<String>, sqlx::Error> = sqlx::query_scalar(
+            "UPDATE proofs SET status = $1, reason = $2, error_code = $3 WHERE status = $4 AND julianday(created_at) <= julianday($5) AND (instance_id IS NULL OR instance_id = $6) RETURNING request_id",
+        )
+        .bind(Status::Failed)
+        .bind(error.to_string())
+        .bind(error.code())
+        .bind(Status::Blocked)
+        .bind(cutoff)
+        .bind(&self.instance_id)
+        .fetch_all(&self.db)
+        .await;
+        match failed {
+            Ok(uuids) => Ok(uuids.iter().filter_map(|uuid| uuid.parse().ok()).collect()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not fail blocked proofs");
+                metrics::counter!("db_errors_total", "query" => "fail_blocked_proofs").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn find_cached(
+        &self,
+        cache_key: &[u8],
+        max_age: Duration,
+    ) -> Result<Option<ProofRecord>, Error> {
+        let cutoff = Utc::now() - max_age;
+        match sqlx::query(&format!(
+            "SELECT {} FROM proofs WHERE cache_key = $1 AND status = $2 AND proof IS NOT NULL AND julianday(proof_generated_at) > julianday($3) ORDER BY julianday(proof_generated_at) DESC LIMIT 1",
+            COLUMNS
+        ))
+        .bind(cache_key)
+        .bind(Status::ProofGenerated)
+        .bind(cutoff)
+        .try_map(record)
+        .fetch_optional(&self.db)
+        .await
+        {
+            Ok(record) => Ok(record),
+            Err(e) => {
+                tracing::error!(error = %e, "could not look up cached proof");
+                metrics::counter!("db_errors_total", "query" => "find_cached").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn redact_proofs(
+        &self,
+        proof_type: ProofType,
+        older_than: Duration,
+    ) -> Result<u64, Error> {
+        let cutoff = Utc::now() - older_than;
+        match sqlx::query(
+            "UPDATE proofs SET proof = NULL, public_inputs = NULL, public_signals = NULL, endpoint = NULL, identifier = NULL, identifier_key_hash = NULL, reason = NULL, error_data = NULL, cache_key = NULL, cache_seal = NULL, redacted_at = $1 WHERE proof_type = $2 AND status IN ($3, $4, $5) AND redacted_at IS NULL AND julianday(COALESCE(proof_generated_at, created_at)) < julianday($6)",
+        )
+        .bind(Utc::now())
+        .bind(proof_type)
+        .bind(Status::ProofGenerated)
+        .bind(Status::Failed)
+        .bind(Status::Cancelled)
+        .bind(cutoff)
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not redact proofs");
+                metrics::counter!("db_errors_total", "query" => "redact_proofs").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn delete_proofs(
+        &self,
+        proof_type: ProofType,
+        older_than: Duration,
+    ) -> Result<u64, Error> {
+        let cutoff = Utc::now() - older_than;
+        match sqlx::query(
+            "DELETE FROM proofs WHERE proof_type = $1 AND status IN ($2, $3, $4) AND julianday(created_at) < julianday($5)",
+        )
+        .bind(proof_type)
+        .bind(Status::ProofGenerated)
+        .bind(Status::Failed)
+        .bind(Status::Cancelled)
+        .bind(cutoff)
+        .execute(&self.db)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(e) => {
+                tracing::error!(error = %e, "could not delete proofs");
+                metrics::counter!("db_errors_total", "query" => "delete_proofs").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn get(&self, uuid: uuid::Uuid) -> Result<Option<ProofRecord>, Error> {
+        match sqlx::query(&format!(
+            "SELECT {} FROM proofs WHERE request_id = $1",
+            COLUMNS
+        ))
+        .bind(uuid.to_string())
+        .try_map(record)
+        .fetch_optional(&self.db)
+        .await
+        {
+            Ok(record) => Ok(record),
+            Err(e) => {
+                tracing::error!(error = %e, "could not get proof");
+                metrics::counter!("db_errors_total", "query" => "get_proof").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn find_by_identifier(
+        &self,
+        identifier: &str,
+        identifier_key_hash: &[u8],
+        before: Option<uuid::Uuid>,
+        limit: u32,
+    ) -> Result<Vec<ProofRecord>, Error> {
+        match sqlx::query(&format!(
+            "SELECT {} FROM proofs WHERE identifier = $1 AND identifier_key_hash = $2 AND ($3 IS NULL OR (julianday(created_at), request_id) < (SELECT julianday(created_at), request_id FROM proofs WHERE request_id = $3)) ORDER BY julianday(created_at) DESC, request_id DESC LIMIT $4",
+            COLUMNS
+        ))
+        .bind(identifier)
+        .bind(identifier_key_hash)
+        .bind(before.map(|uuid| uuid.to_string()))
+        .bind(i64::from(limit))
+        .try_map(record)
+        .fetch_all(&self.db)
+        .await
+        {
+            Ok(records) => Ok(records),
+            Err(e) => {
+                tracing::error!(error = %e, "could not find proofs by identifier");
+                metrics::counter!("db_errors_total", "query" => "find_by_identifier")
+                    .increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn find_batch(&self, batch_id: uuid::Uuid) -> Result<Vec<ProofRecord>, Error> {
+        match sqlx::query(&format!(
+            "SELECT {} FROM proofs WHERE batch_id = $1 ORDER BY batch_index",
+            COLUMNS
+        ))
+        .bind(batch_id.to_string())
+        .try_map(record)
+        .fetch_all(&self.db)
+        .await
+        {
+            Ok(records) => Ok(records),
+            Err(e) => {
+                tracing::error!(error = %e, "could not find batch");
+                metrics::counter!("db_errors_total", "query" => "find_batch").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn list(&self, status: Option<Status>, limit: u32) -> Result<Vec<ProofRecord>, Error> {
+        match sqlx::query(&format!(
+            "SELECT {} FROM proofs WHERE $1 IS NULL OR status = $1 ORDER BY julianday(created_at) DESC LIMIT $2",
+            COLUMNS
+        ))
+        .bind(status)
+        .bind(i64::from(limit))
+        .try_map(record)
+        .fetch_all(&self.db)
+        .await
+        {
+            Ok(records) => Ok(records),
+            Err(e) => {
+                tracing::error!(error = %e, "could not list proofs");
+                metrics::counter!("db_errors_total", "query" => "list_proofs").increment(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<i64, Error> {
+        migrations::run(&migrations::SQLITE, &self.db).await?;
+        Ok(migrations::version(&migrations::SQLITE))
+    }
+
+    async fn check_schema(&self) -> Result<(), Error> {
+        let has_migrations: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+        )
+        .fetch_one(&self.db)
+        .await?;
+        let applied: Vec<(i64, bool, Vec<u8>)> = if has_migrations {
+            sqlx::query_as(
+                "SELECT version, success, checksum FROM _sqlx_migrations ORDER BY version",
+            )
+            .fetch_all(&self.db)
+            .await?
+        } else {
+            Vec::new()
+        };
+        migrations::check(&migrations::SQLITE, &applied)
+    }
+}
+
+//single requests and the requests of a batch, which are inserted in one transaction
+async fn insert<'e>(
+    db: impl sqlx::Executor<'e, Database = Sqlite>,
+    instance_id: &str,
+    proof: &NewProof<'_>,
+    batch: Option<(uuid::Uuid, i32)>,
+) -> Result<(), Error> {
+    let now = Utc::now();
+
+    sqlx::query(
+        "INSERT INTO proofs (proof_type, request_id, status, created_at, circuit_name, onchain, endpoint_type, endpoint, identifier, payload_digest, cache_key, batch_id, batch_index, depends_on, proof, public_inputs, public_signals, proof_generated_at, instance_id, identifier_key_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)",
+    )
+    .bind(proof.proof_type)
+    .bind(proof.uuid.to_string())
+    .bind(proof.status())
+    .bind(now)
+    .bind(proof.circuit_name)
+    .bind(proof.onchain)
+    .bind(proof.endpoint_type)
+    .bind(proof.endpoint)
+    .bind(proof.identifier)
+    .bind(proof.payload_digest)
+    .bind(proof.cache_key)
+    .bind(batch.map(|(batch_id, _)| batch_id.to_string()))
+    .bind(batch.map(|(_, batch_index)| batch_index))
+    .bind(proof.depends_on.map(|depends_on| depends_on.to_string()))
+    .bind(proof.cached.as_ref().map(|cached| Json(cached.proof)))
+    .bind(proof.cached.as_ref().map(|cached| Json(cached.public_inputs)))
+    .bind(proof.cached.as_ref().and_then(|cached| cached.public_signals.map(Json)))
+    .bind(proof.cached.as_ref().map(|_| now))
+    .bind(instance_id)
+    .bind(proof.identifier_key_hash)
+    .execute(db)
+    .await
+    .map_err(|e| {
+        tracing::error!(error = %e, "could not create the record");
+        metrics::counter!("db_errors_total", "query" => "create_proof_status").increment(1);
+        match &e {
+            sqlx::Error::Database(db_error) if db_error.is_unique_violation() => Error::DuplicateUuid,
+            _ => e.into(),
+        }
+    })?;
+
+    Ok(())
+}
+
+fn record(row: SqliteRow) -> Result<ProofRecord, sqlx::Error> {
+    let request_id: String = row.try_get("request_id")?;
+    let batch_id: Option<String> = row.try_get("batch_id")?;
+    let depends_on: Option<String> = row.try_get("depends_on")?;
+    Ok(ProofRecord {
+        request_id: request_id.parse().map_err(|e| sqlx::Error::ColumnDecode {
+            index: "request_id".to_string(),
+            source: Box::new(e),
+        })?,
+        proof_type: row.try_get("proof_type")?,
+        status: row
+            .try_get::<Option<Status>, _>("status")?
+            .unwrap_or(Status::Pending),
+        circuit_name: row.try_get("circuit_name")?,
+        onchain: row.try_get("onchain")?,
+        created_at: row.try_get("created_at")?,
+        witness_generated_at: row.try_get("witness_generated_at")?,
+        proof_generated_at: row.try_get("proof_generated_at")?,
+        proof: row
+            .try_get::<Option<Json<Proof>>, _>("proof")?
+            .map(|proof| proof.0),
+        endpoint_type: row.try_get("endpoint_type")?,
+        endpoint: row.try_get("endpoint")?,
+        public_inputs: row
+            .try_get::<Option<Json<Vec<String>>>, _>("public_inputs")?
+            .map(|public_inputs| public_inputs.0),
+        public_signals: row
+            .try_get::<Option<Json<serde_json::Value>>, _>("public_signals")?
+            .map(|signals| signals.0),
+        reason: row.try_get("reason")?,
+        identifier: row.try_get("identifier")?,
+        identifier_key_hash: row.try_get("identifier_key_hash")?,
+        attempts: row.try_get("attempts")?,
+        error_code: row.try_get("error_code")?,
+        error_data: row
+            .try_get::<Option<Json<serde_json::Value>>, _>("error_data")?
+            .map(|data| data.0),
+        payload_digest: row.try_get("payload_digest")?,
+        cache_key: row.try_get("cache_key")?,
+        cache_seal: row.try_get("cache_seal")?,
+        redacted_at: row.try_get("redacted_at")?,
+        batch_id: batch_id
+            .map(|batch_id| batch_id.parse())
+            .transpose()
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "batch_id".to_string(),
+                source: Box::new(e),
+            })?,
+        batch_index: row.try_get("batch_index")?,
+        depends_on: depends_on
+            .map(|depends_on| depends_on.parse())
+            .transpose()
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "depends_on".to_string(),
+                source: Box::new(e),
+            })?,
+        unblocked_at: row.try_get("unblocked_at")?,
+    })
+}
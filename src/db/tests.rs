@@ -0,0 +1,515 @@
+use std::ops::Deref;
+use std::time::Duration;
+
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{Pool, Sqlite};
+
+use super::memory::MemoryStore;
+use super::sqlite::SqliteStore;
+use super::types::Status;
+use super::{NewProof, Proof, ProofStore};
+use crate::error::Error;
+use crate::types::ProofType;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+//the stores that do not need a server; ages are set by backdating the timestamps
+//rather than by waiting
+enum TestStore {
+    Memory(MemoryStore),
+    Sqlite(SqliteStore),
+}
+
+impl TestStore {
+    fn name(&self) -> &'static str {
+        match self {
+            TestStore::Memory(_) => "memory",
+            TestStore::Sqlite(_) => "sqlite",
+        }
+    }
+
+    async fn backdate(&self, uuid: uuid::Uuid, by: Duration) {
+        match self {
+            TestStore::Memory(store) => store.backdate(uuid, by).await,
+            TestStore::Sqlite(store) => store.backdate(uuid, by).await,
+        }
+    }
+}
+
+impl Deref for TestStore {
+    type Target = dyn ProofStore;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            TestStore::Memory(store) => store,
+            TestStore::Sqlite(store) => store,
+        }
+    }
+}
+
+//a single connection, every connection to `:memory:` opens a new database
+async fn sqlite_pool() -> Pool<Sqlite> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    SqliteStore::new(pool.clone(), String::new())
+        .migrate()
+        .await
+        .unwrap();
+    pool
+}
+
+async fn stores() -> Vec<TestStore> {
+    let sqlite = SqliteStore::new(sqlite_pool().await, "test".to_string());
+    vec![
+        TestStore::Memory(MemoryStore::default()),
+        TestStore::Sqlite(sqlite),
+    ]
+}
+
+fn new_proof(uuid: uuid::Uuid) -> NewProof<'static> {
+    NewProof {
+        uuid,
+        proof_type: ProofType::Register,
+        circuit_name: "register_sha256_sha256_sha256_rsa_65537_4096",
+        onchain: true,
+        endpoint_type: None,
+        endpoint: None,
+        payload_digest: b"digest",
+        identifier: None,
+        identifier_key_hash: None,
+        cache_key: None,
+        depends_on: None,
+        blocked: false,
+        cached: None,
+    }
+}
+
+fn proof() -> Proof {
+    Proof {
+        pi_a: vec!["1".to_string(), "2".to_string()],
+        pi_b: vec![vec!["3".to_string()], vec!["4".to_string()]],
+        pi_c: vec!["5".to_string()],
+        protocol: "groth16".to_string(),
+    }
+}
+
+async fn status(db: &dyn ProofStore, uuid: uuid::Uuid) -> Option<Status> {
+    db.get(uuid).await.unwrap().map(|record| record.status)
+}
+
+#[tokio::test]
+async fn create_rejects_duplicates() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let uuid = uuid::Uuid::new_v4();
+        db.create(&new_proof(uuid)).await.unwrap();
+        assert!(
+            matches!(db.create(&new_proof(uuid)).await, Err(Error::DuplicateUuid)),
+            "{name}"
+        );
+        let record = db.get(uuid).await.unwrap().unwrap();
+        assert_eq!(record.status, Status::Pending, "{name}");
+        assert_eq!(
+            record.payload_digest.as_deref(),
+            Some(&b"digest"[..]),
+            "{name}"
+        );
+        assert_eq!(status(db, uuid::Uuid::new_v4()).await, None, "{name}");
+    }
+}
+
+#[tokio::test]
+async fn backdate_moves_timestamps() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let uuid = uuid::Uuid::new_v4();
+        db.create(&new_proof(uuid)).await.unwrap();
+        db.update_proof(uuid, &proof(), &["1".to_string()], None, None)
+            .await
+            .unwrap();
+        let before = db.get(uuid).await.unwrap().unwrap();
+        store.backdate(uuid, HOUR).await;
+        let after = db.get(uuid).await.unwrap().unwrap();
+        assert_eq!(
+            after.created_at.unwrap(),
+            before.created_at.unwrap() - HOUR,
+            "{name}"
+        );
+        assert_eq!(
+            after.proof_generated_at.unwrap(),
+            before.proof_generated_at.unwrap() - HOUR,
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn cancelled_requests_stay_cancelled() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let uuid = uuid::Uuid::new_v4();
+        db.create(&new_proof(uuid)).await.unwrap();
+        db.cancel_proof(uuid).await.unwrap();
+
+        db.set_witness_generated(uuid).await.unwrap();
+        db.update_proof(uuid, &proof(), &["1".to_string()], None, None)
+            .await
+            .unwrap();
+        db.fail_proof(uuid, &Error::Abandoned).await.unwrap();
+        let record = db.get(uuid).await.unwrap().unwrap();
+        assert_eq!(record.status, Status::Cancelled, "{name}");
+        assert!(record.proof.is_none(), "{name}");
+        assert_eq!(record.error_code, None, "{name}");
+
+        //nor can a finished request be cancelled
+        let uuid = uuid::Uuid::new_v4();
+        db.create(&new_proof(uuid)).await.unwrap();
+        db.update_proof(uuid, &proof(), &["1".to_string()], None, None)
+            .await
+            .unwrap();
+        db.cancel_proof(uuid).await.unwrap();
+        db.fail_proof(uuid, &Error::Abandoned).await.unwrap();
+        assert_eq!(
+            status(db, uuid).await,
+            Some(Status::ProofGenerated),
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn batches_are_recorded_once_and_whole() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let existing = uuid::Uuid::new_v4();
+        db.create(&new_proof(existing)).await.unwrap();
+
+        let batch_id = uuid::Uuid::new_v4();
+        let batch = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+        db.create_batch(batch_id, &batch.map(new_proof))
+            .await
+            .unwrap();
+        let records = db.find_batch(batch_id).await.unwrap();
+        let uuids: Vec<uuid::Uuid> = records.iter().map(|record| record.request_id).collect();
+        assert_eq!(uuids, batch, "{name}");
+        let indexes: Vec<Option<i32>> = records.iter().map(|record| record.batch_index).collect();
+        assert_eq!(indexes, [Some(0), Some(1)], "{name}");
+
+        let fresh = uuid::Uuid::new_v4();
+        assert!(
+            matches!(
+                db.create_batch(batch_id, &[new_proof(fresh)]).await,
+                Err(Error::DuplicateUuid)
+            ),
+            "{name}"
+        );
+        let other_batch = uuid::Uuid::new_v4();
+        assert!(
+            matches!(
+                db.create_batch(other_batch, &[new_proof(fresh), new_proof(existing)])
+                    .await,
+                Err(Error::DuplicateUuid)
+            ),
+            "{name}"
+        );
+        assert_eq!(status(db, fresh).await, None, "{name}");
+        assert!(
+            db.find_batch(other_batch).await.unwrap().is_empty(),
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn retention_redacts_then_deletes_finished_requests() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let finished = uuid::Uuid::new_v4();
+        let recent = uuid::Uuid::new_v4();
+        let pending = uuid::Uuid::new_v4();
+        let other_type = uuid::Uuid::new_v4();
+        db.create(&NewProof {
+            identifier: Some("0x1234"),
+            identifier_key_hash: Some(b"hash"),
+            cache_key: Some(b"key"),
+            ..new_proof(finished)
+        })
+        .await
+        .unwrap();
+        db.create(&new_proof(recent)).await.unwrap();
+        db.create(&new_proof(pending)).await.unwrap();
+        db.create(&NewProof {
+            proof_type: ProofType::Dsc,
+            ..new_proof(other_type)
+        })
+        .await
+        .unwrap();
+        for uuid in [finished, recent, other_type] {
+            db.update_proof(uuid, &proof(), &["1".to_string()], None, None)
+                .await
+                .unwrap();
+        }
+        for uuid in [finished, pending, other_type] {
+            store.backdate(uuid, 2 * HOUR).await;
+        }
+
+        let redacted = db.redact_proofs(ProofType::Register, HOUR).await;
+        assert_eq!(redacted.unwrap(), 1, "{name}");
+        let record = db.get(finished).await.unwrap().unwrap();
+        assert_eq!(record.status, Status::ProofGenerated, "{name}");
+        assert!(record.proof.is_none(), "{name}");
+        assert!(record.public_inputs.is_none(), "{name}");
+        assert!(record.identifier.is_none(), "{name}");
+        assert!(record.identifier_key_hash.is_none(), "{name}");
+        assert!(record.cache_key.is_none(), "{name}");
+        assert!(record.redacted_at.is_some(), "{name}");
+        assert!(
+            db.get(recent).await.unwrap().unwrap().proof.is_some(),
+            "{name}"
+        );
+        let redacted = db.redact_proofs(ProofType::Register, HOUR).await;
+        assert_eq!(redacted.unwrap(), 0, "{name}");
+
+        let deleted = db.delete_proofs(ProofType::Register, HOUR).await;
+        assert_eq!(deleted.unwrap(), 1, "{name}");
+        assert_eq!(status(db, finished).await, None, "{name}");
+        assert_eq!(
+            status(db, recent).await,
+            Some(Status::ProofGenerated),
+            "{name}"
+        );
+        assert_eq!(status(db, pending).await, Some(Status::Pending), "{name}");
+        let record = db.get(other_type).await.unwrap().unwrap();
+        assert!(record.proof.is_some(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn find_by_identifier_needs_the_key_and_pages() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let listed = |uuid| NewProof {
+            identifier: Some("app"),
+            identifier_key_hash: Some(b"hash"),
+            ..new_proof(uuid)
+        };
+        let old = uuid::Uuid::new_v4();
+        let other_key = uuid::Uuid::new_v4();
+        let (batch_id, first, second) = (
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        );
+        db.create(&listed(old)).await.unwrap();
+        store.backdate(old, HOUR).await;
+        db.create(&NewProof {
+            identifier_key_hash: Some(b"other"),
+            ..listed(other_key)
+        })
+        .await
+        .unwrap();
+        //the requests of a batch may share their creation time
+        db.create_batch(batch_id, &[listed(first), listed(second)])
+            .await
+            .unwrap();
+
+        let uuids = |records: Vec<super::types::ProofRecord>| -> Vec<uuid::Uuid> {
+            records.iter().map(|record| record.request_id).collect()
+        };
+        let all = db.find_by_identifier("app", b"hash", None, 10).await;
+        let all = uuids(all.unwrap());
+        assert_eq!(all.len(), 3, "{name}");
+        assert_eq!(all[2], old, "{name}");
+
+        let mut paged = Vec::new();
+        let mut before = None;
+        loop {
+            let page = db.find_by_identifier("app", b"hash", before, 1).await;
+            let page = uuids(page.unwrap());
+            let Some(last) = page.last() else { break };
+            before = Some(*last);
+            paged.extend(page);
+        }
+        assert_eq!(paged, all, "{name}");
+
+        let others = db.find_by_identifier("app", b"other", None, 10).await;
+        assert_eq!(uuids(others.unwrap()), [other_key], "{name}");
+        let wrong_key = db.find_by_identifier("app", b"wrong", None, 10).await;
+        assert!(wrong_key.unwrap().is_empty(), "{name}");
+        let gone = db
+            .find_by_identifier("app", b"hash", Some(uuid::Uuid::new_v4()), 10)
+            .await;
+        assert!(gone.unwrap().is_empty(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn find_cached_returns_recent_proofs_with_their_seal() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let cacheable = |uuid| NewProof {
+            cache_key: Some(b"key"),
+            ..new_proof(uuid)
+        };
+        let (old, recent, pending) = (
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        );
+        for uuid in [old, recent, pending] {
+            db.create(&cacheable(uuid)).await.unwrap();
+        }
+        for uuid in [old, recent] {
+            db.update_proof(uuid, &proof(), &["1".to_string()], None, Some(b"seal"))
+                .await
+                .unwrap();
+        }
+        store.backdate(old, 2 * HOUR).await;
+
+        let found = db.find_cached(b"key", HOUR).await.unwrap().unwrap();
+        assert_eq!(found.request_id, recent, "{name}");
+        assert_eq!(found.cache_seal.as_deref(), Some(&b"seal"[..]), "{name}");
+        let found = db.find_cached(b"key", 3 * HOUR).await.unwrap().unwrap();
+        assert_eq!(found.request_id, recent, "{name}");
+
+        store.backdate(recent, 2 * HOUR).await;
+        assert!(
+            db.find_cached(b"key", HOUR).await.unwrap().is_none(),
+            "{name}"
+        );
+        assert!(
+            db.find_cached(b"other", 3 * HOUR).await.unwrap().is_none(),
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn fail_stuck_proofs_skips_active_and_other_requests() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let stuck = uuid::Uuid::new_v4();
+        let witness_generated = uuid::Uuid::new_v4();
+        let recent = uuid::Uuid::new_v4();
+        let active = uuid::Uuid::new_v4();
+        let other_type = uuid::Uuid::new_v4();
+        let finished = uuid::Uuid::new_v4();
+        for uuid in [stuck, witness_generated, recent, active, finished] {
+            db.create(&new_proof(uuid)).await.unwrap();
+        }
+        db.create(&NewProof {
+            proof_type: ProofType::Dsc,
+            ..new_proof(other_type)
+        })
+        .await
+        .unwrap();
+        db.set_witness_generated(witness_generated).await.unwrap();
+        db.update_proof(finished, &proof(), &["1".to_string()], None, None)
+            .await
+            .unwrap();
+        for uuid in [stuck, witness_generated, active, other_type, finished] {
+            store.backdate(uuid, 2 * HOUR).await;
+        }
+
+        let failed = db
+            .fail_stuck_proofs(HOUR, &[ProofType::Register], &[active])
+            .await
+            .unwrap();
+        assert_eq!(failed, 2, "{name}");
+        for uuid in [stuck, witness_generated] {
+            let record = db.get(uuid).await.unwrap().unwrap();
+            assert_eq!(record.status, Status::Failed, "{name}");
+            assert_eq!(record.error_code, Some(Error::Abandoned.code()), "{name}");
+        }
+        assert_eq!(status(db, recent).await, Some(Status::Pending), "{name}");
+        assert_eq!(status(db, active).await, Some(Status::Pending), "{name}");
+        assert_eq!(
+            status(db, other_type).await,
+            Some(Status::Pending),
+            "{name}"
+        );
+        assert_eq!(
+            status(db, finished).await,
+            Some(Status::ProofGenerated),
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn blocked_requests_fail_once_too_old() {
+    for store in stores().await {
+        let (name, db) = (store.name(), &*store);
+        let old = uuid::Uuid::new_v4();
+        let recent = uuid::Uuid::new_v4();
+        let unblocked = uuid::Uuid::new_v4();
+        for uuid in [old, recent, unblocked] {
+            db.create(&NewProof {
+                blocked: true,
+                ..new_proof(uuid)
+            })
+            .await
+            .unwrap();
+        }
+        assert!(db.unblock_proof(unblocked).await.unwrap(), "{name}");
+        assert!(!db.unblock_proof(unblocked).await.unwrap(), "{name}");
+        for uuid in [old, unblocked] {
+            store.backdate(uuid, 2 * HOUR).await;
+        }
+
+        //a blocked request is not stuck while it waits, an unblocked one is aged from then
+        let stuck = db
+            .fail_stuck_proofs(HOUR, &[ProofType::Register], &[])
+            .await
+            .unwrap();
+        assert_eq!(stuck, 1, "{name}");
+        assert_eq!(status(db, old).await, Some(Status::Blocked), "{name}");
+
+        let failed = db.fail_blocked_proofs(HOUR).await.unwrap();
+        assert_eq!(failed, [old], "{name}");
+        let record = db.get(old).await.unwrap().unwrap();
+        assert_eq!(record.status, Status::Failed, "{name}");
+        assert_eq!(record.error_code, Some(Error::Abandoned.code()), "{name}");
+        assert_eq!(status(db, recent).await, Some(Status::Blocked), "{name}");
+
+        //on startup every blocked request is failed
+        let failed = db.fail_blocked_proofs(Duration::ZERO).await.unwrap();
+        assert_eq!(failed, [recent], "{name}");
+        assert_eq!(status(db, recent).await, Some(Status::Failed), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn fail_stuck_proofs_skips_requests_of_other_servers() {
+    let pool = sqlite_pool().await;
+    let db = SqliteStore::new(pool.clone(), "a".to_string());
+    let other = SqliteStore::new(pool.clone(), "b".to_string());
+    let own = uuid::Uuid::new_v4();
+    let others = uuid::Uuid::new_v4();
+    let legacy = uuid::Uuid::new_v4();
+    for uuid in [own, legacy] {
+        db.create(&new_proof(uuid)).await.unwrap();
+    }
+    other.create(&new_proof(others)).await.unwrap();
+    //recorded before requests had an owner
+    sqlx::query("UPDATE proofs SET instance_id = NULL WHERE request_id = $1")
+        .bind(legacy.to_string())
+        .execute(&pool)
+        .await
+        .unwrap();
+    for uuid in [own, others, legacy] {
+        db.backdate(uuid, 2 * HOUR).await;
+    }
+
+    let failed = db
+        .fail_stuck_proofs(HOUR, &[ProofType::Register], &[])
+        .await
+        .unwrap();
+    assert_eq!(failed, 2);
+    assert_eq!(status(&db, own).await, Some(Status::Failed));
+    assert_eq!(status(&db, legacy).await, Some(Status::Failed));
+    assert_eq!(status(&db, others).await, Some(Status::Pending));
+}
@@ -0,0 +1,207 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::error::Error;
+use crate::transport::Stream;
+
+//length 8 followed by the SSLRequest code 80877103
+const SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+
+/// TLS to the database, done by the bridge rather than by sqlx so that every connection
+/// is checked against the pinned certificate and a failed handshake never falls back to
+/// plain text.
+#[derive(Clone)]
+pub struct DatabaseTls {
+    connector: TlsConnector,
+}
+
+impl DatabaseTls {
+    /// Refuses a connection that verifies nothing, neither a CA nor a fingerprint, unless
+    /// `insecure` is set, and then returns `None`. With only a fingerprint the certificate
+    /// chain is not verified, the pin alone is trusted.
+    pub fn new(
+        ca_file: Option<&Path>,
+        fingerprint: Option<&str>,
+        insecure: bool,
+    ) -> Result<Option<Self>, Error> {
+        if ca_file.is_none() && fingerprint.is_none() {
+            if insecure {
+                return Ok(None);
+            }
+            return Err(tls_error(
+                "a database CA file or certificate fingerprint is required, --database-insecure skips the check for local development".to_string(),
+            ));
+        }
+
+        let mut roots = Vec::new();
+        if let Some(ca_file) = ca_file {
+            for certificate in CertificateDer::pem_file_iter(ca_file)
+                .map_err(|e| tls_error(format!("could not read {}: {}", ca_file.display(), e)))?
+            {
+                roots.push(certificate.map_err(|e| {
+                    tls_error(format!("could not read {}: {}", ca_file.display(), e))
+                })?);
+            }
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = PinnedVerifier::new(roots, fingerprint, &provider)?;
+        let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| tls_error(e.to_string()))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+
+        Ok(Some(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+        }))
+    }
+
+    /// Asks the server to switch to TLS and verifies it as `host`.
+    pub async fn connect(
+        &self,
+        mut stream: Stream,
+        host: &str,
+    ) -> std::io::Result<TlsStream<Stream>> {
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+        stream.write_all(&SSL_REQUEST).await?;
+        if stream.read_u8().await? != b'S' {
+            return Err(std::io::Error::other("the database does not accept tls"));
+        }
+        self.connector.connect(server_name, stream).await
+    }
+}
+
+fn tls_error(message: String) -> Error {
+    Error::Database {
+        message,
+        retryable: false,
+    }
+}
+
+#[derive(Debug)]
+struct PinnedVerifier {
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    fingerprint: Option<Vec<u8>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedVerifier {
+    fn new(
+        roots: Vec<CertificateDer<'static>>,
+        fingerprint: Option<&str>,
+        provider: &Arc<CryptoProvider>,
+    ) -> Result<Self, Error> {
+        let webpki = if roots.is_empty() {
+            None
+        } else {
+            let mut store = RootCertStore::empty();
+            for root in roots {
+                store
+                    .add(root)
+                    .map_err(|e| tls_error(format!("invalid database root certificate: {}", e)))?;
+            }
+            let verifier =
+                WebPkiServerVerifier::builder_with_provider(Arc::new(store), Arc::clone(provider))
+                    .build()
+                    .map_err(|e| tls_error(e.to_string()))?;
+            Some(verifier)
+        };
+
+        Ok(Self {
+            webpki,
+            fingerprint: fingerprint.map(decode_fingerprint).transpose()?,
+            provider: Arc::clone(provider),
+        })
+    }
+}
+
+//the fingerprint is validated as 64 hex digits when the config is loaded
+fn decode_fingerprint(fingerprint: &str) -> Result<Vec<u8>, Error> {
+    (0..fingerprint.len())
+        .step_by(2)
+        .map(|i| {
+            fingerprint
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| tls_error(format!("invalid certificate fingerprint {}", fingerprint)))
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(webpki) = &self.webpki {
+            webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if let Some(fingerprint) = &self.fingerprint {
+            if Sha256::digest(end_entity).as_slice() != fingerprint.as_slice() {
+                return Err(rustls::Error::General(
+                    "database certificate does not match the pinned fingerprint".to_string(),
+                ));
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
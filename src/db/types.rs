@@ -1,17 +1,120 @@
+use serde::Serialize;
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Database, Decode, Encode, Type};
+
+use super::Proof;
+use crate::types::{EndpointType, ProofType};
+
+//stored as a SMALLINT, the values must not change
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(i16)]
 pub enum Status {
-    Pending,
-    WitnessGenerated,
-    ProofGenererated,
-    Failed,
+    Pending = 0,
+    WitnessGenerated = 1,
+    ProofGenerated = 2,
+    Failed = 3,
+    Cancelled = 4,
+    /// Waiting for the request it depends on to generate its proof.
+    Blocked = 5,
 }
 
-impl Into<i32> for Status {
-    fn into(self) -> i32 {
-        match self {
-            Status::Pending => 0,
-            Status::WitnessGenerated => 1,
-            Status::ProofGenererated => 2,
-            Status::Failed => 3,
-        }
+impl Status {
+    /// Whether the request is still in the pipeline.
+    pub fn is_in_progress(&self) -> bool {
+        matches!(
+            self,
+            Status::Pending | Status::WitnessGenerated | Status::Blocked
+        )
+    }
+
+    /// The status of a batch as a whole: failed or cancelled as soon as one request is,
+    /// otherwise that of its least advanced request.
+    pub fn of_batch(statuses: &[Status]) -> Status {
+        [
+            Status::Failed,
+            Status::Cancelled,
+            Status::Blocked,
+            Status::Pending,
+            Status::WitnessGenerated,
+        ]
+        .into_iter()
+        .find(|status| statuses.contains(status))
+        .unwrap_or(Status::ProofGenerated)
+    }
+}
+
+//stored as the lowercase name, like in the requests
+impl<DB: Database> Type<DB> for EndpointType
+where
+    str: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <str as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <str as Type<DB>>::compatible(ty)
     }
 }
+
+impl<'q, DB: Database> Encode<'q, DB> for EndpointType
+where
+    &'q str: Encode<'q, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, BoxDynError> {
+        let name = match self {
+            EndpointType::Celo => "celo",
+            EndpointType::Https => "https",
+        };
+        <&str as Encode<'q, DB>>::encode(name, buf)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for EndpointType
+where
+    &'r str: Decode<'r, DB>,
+{
+    fn decode(value: <DB as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+        let name = <&str as Decode<'r, DB>>::decode(value)?;
+        Ok(serde_plain::from_str(name)?)
+    }
+}
+
+/// A row of the `proofs` table, as read back from any of the stores.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct ProofRecord {
+    pub request_id: uuid::Uuid,
+    pub proof_type: ProofType,
+    pub status: Status,
+    pub circuit_name: String,
+    pub onchain: bool,
+    pub created_at: Option<DateTime<Utc>>,
+    pub witness_generated_at: Option<DateTime<Utc>>,
+    pub proof_generated_at: Option<DateTime<Utc>>,
+    pub proof: Option<Proof>,
+    pub endpoint_type: Option<EndpointType>,
+    pub endpoint: Option<String>,
+    pub public_inputs: Option<Vec<String>>,
+    pub public_signals: Option<serde_json::Value>,
+    pub reason: Option<String>,
+    pub identifier: Option<String>,
+    pub identifier_key_hash: Option<Vec<u8>>,
+    pub attempts: i32,
+    pub error_code: Option<i32>,
+    pub error_data: Option<serde_json::Value>,
+    pub payload_digest: Option<Vec<u8>>,
+    pub cache_key: Option<Vec<u8>>,
+    pub cache_seal: Option<Vec<u8>>,
+    pub redacted_at: Option<DateTime<Utc>>,
+    pub batch_id: Option<uuid::Uuid>,
+    pub batch_index: Option<i32>,
+    pub depends_on: Option<uuid::Uuid>,
+    pub unblocked_at: Option<DateTime<Utc>>,
+}
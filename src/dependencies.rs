@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use crate::db::types::Status;
+use crate::db::ProofStore;
+use crate::error::Error;
+use crate::generator::file_generator::FileGenerator;
+use crate::store::JobStore;
+use crate::utils::{cleanup, discard_job};
+
+//the request depended on may be proved by another server, which does not wake us up
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Blocked {
+    depends_on: uuid::Uuid,
+    file_generator: FileGenerator,
+}
+
+/// Accepted requests that wait for the proof of the request they depend on before
+/// entering the pipeline. They are already registered as jobs, so they can be cancelled.
+#[derive(Default)]
+pub struct Dependencies {
+    blocked: Mutex<HashMap<uuid::Uuid, Blocked>>,
+}
+
+impl Dependencies {
+    pub async fn hold(&self, depends_on: uuid::Uuid, file_generator: FileGenerator) {
+        let mut blocked = self.blocked.lock().await;
+        blocked.insert(
+            file_generator.uuid(),
+            Blocked {
+                depends_on,
+                file_generator,
+            },
+        );
+        metrics::gauge!("blocked_requests").set(blocked.len() as f64);
+    }
+}
+
+/// Releases the blocked requests whose dependency has generated its proof and fails
+/// those whose dependency failed, was cancelled or is gone. Checks whenever a job leaves
+/// the pipeline and every few seconds.
+pub async fn run(
+    db: Arc<dyn ProofStore>,
+    jobs: Arc<JobStore>,
+    dependencies: Arc<Dependencies>,
+    file_generator_sender: tokio::sync::mpsc::Sender<FileGenerator>,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = jobs.job_removed() => {}
+        }
+        release(&*db, &jobs, &dependencies, &file_generator_sender).await;
+    }
+}
+
+async fn release(
+    db: &dyn ProofStore,
+    jobs: &JobStore,
+    dependencies: &Dependencies,
+    file_generator_sender: &tokio::sync::mpsc::Sender<FileGenerator>,
+) {
+    //requests blocked in the meantime are added to the map again
+    let blocked = std::mem::take(&mut *dependencies.blocked.lock().await);
+    let mut still_blocked = HashMap::new();
+
+    for (uuid, blocked) in blocked {
+        let span = blocked.file_generator.span();
+        if blocked.file_generator.is_cancelled() {
+            discard_job(uuid, jobs).instrument(span).await;
+            continue;
+        }
+
+        let status = match db.get(blocked.depends_on).await {
+            Ok(record) => record.map(|record| record.status),
+            //the dependency is checked again on the next round
+            Err(_) => {
+                still_blocked.insert(uuid, blocked);
+                continue;
+            }
+        };
+
+        match status {
+            Some(Status::ProofGenerated) => {
+                async {
+                    let depends_on = blocked.depends_on;
+                    match db.unblock_proof(uuid).await {
+                        Ok(true) => {}
+                        //cancelled or failed by the sweeper in the meantime
+                        Ok(false) => return discard_job(uuid, jobs).await,
+                        Err(e) => return cleanup(uuid, db, jobs, &e).await,
+                    }
+                    jobs.unblock(&uuid).await;
+                    if let Err(e) = file_generator_sender.send(blocked.file_generator).await {
+                        return cleanup(uuid, db, jobs, &Error::from(e)).await;
+                    }
+                    tracing::info!(%depends_on, "request unblocked");
+                }
+                .instrument(span)
+                .await;
+            }
+            Some(status) if status.is_in_progress() => {
+                still_blocked.insert(uuid, blocked);
+            }
+            _ => {
+                let error = Error::DependencyFailed {
+                    depends_on: blocked.depends_on,
+                };
+                cleanup(uuid, db, jobs, &error).instrument(span).await;
+            }
+        }
+    }
+
+    let mut blocked = dependencies.blocked.lock().await;
+    blocked.extend(still_blocked);
+    metrics::gauge!("blocked_requests").set(blocked.len() as f64);
+}
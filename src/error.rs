@@ -0,0 +1,245 @@
+use std::io::ErrorKind;
+
+use jsonrpsee::types::ErrorObjectOwned;
+use serde_json::json;
+
+use crate::types::HealthResponse;
+
+/// Every failure the server reports, either as a JSON-RPC error or in the `reason`
+/// column of a failed proof. The numeric codes are stable and must not be reused.
+#[derive(Debug)]
+pub enum Error {
+    //request errors
+    InvalidPublicKeyLength {
+        length: usize,
+    },
+    InvalidPublicKey(String),
+    DuplicateUuid,
+    UuidNotFound,
+    DecryptionFailed,
+    InvalidProofRequest(String),
+    ProofTypeNotAllowed {
+        allowed: String,
+    },
+    CircuitNotSupported {
+        circuit: String,
+    },
+    InvalidCancelToken,
+    AlreadyCancelled,
+    PayloadConflict,
+    InvalidIdentifier(String),
+    InvalidDependency(String),
+
+    //enclave errors
+    Attestation(String),
+    InvalidSharedSecret,
+    QueueClosed,
+    Database {
+        message: String,
+        retryable: bool,
+    },
+    Unhealthy(Box<HealthResponse>),
+    Shutdown,
+
+    //pipeline errors
+    Io {
+        message: String,
+        retryable: bool,
+    },
+    CircuitNotFound {
+        circuit: String,
+    },
+    WitnessGenerationFailed(String),
+    WitnessNotFound,
+    ProofGenerationFailed(String),
+    InvalidProofOutput(String),
+    Timeout {
+        stage: &'static str,
+        timeout_secs: u64,
+    },
+    Cancelled,
+    Abandoned,
+    DependencyFailed {
+        depends_on: uuid::Uuid,
+    },
+}
+
+impl Error {
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::InvalidPublicKeyLength { .. } => 1001,
+            Error::InvalidPublicKey(_) => 1002,
+            Error::DuplicateUuid => 1003,
+            Error::UuidNotFound => 1004,
+            Error::DecryptionFailed => 1005,
+            Error::InvalidProofRequest(_) => 1006,
+            Error::ProofTypeNotAllowed { .. } => 1007,
+            Error::CircuitNotSupported { .. } => 1008,
+            Error::InvalidCancelToken => 1009,
+            Error::AlreadyCancelled => 1010,
+            Error::PayloadConflict => 1011,
+            Error::InvalidIdentifier(_) => 1012,
+            Error::InvalidDependency(_) => 1013,
+
+            Error::Attestation(_) => 2001,
+            Error::InvalidSharedSecret => 2002,
+            Error::QueueClosed => 2003,
+            Error::Database { .. } => 2004,
+            Error::Unhealthy(_) => 2005,
+            Error::Shutdown => 2006,
+
+            Error::Io { .. } => 3001,
+            Error::CircuitNotFound { .. } => 3002,
+            Error::WitnessGenerationFailed(_) => 3003,
+            Error::WitnessNotFound => 3004,
+            Error::ProofGenerationFailed(_) => 3005,
+            Error::InvalidProofOutput(_) => 3006,
+            Error::Timeout { .. } => 3007,
+            Error::Cancelled => 3008,
+            Error::Abandoned => 3009,
+            Error::DependencyFailed { .. } => 3010,
+        }
+    }
+
+    /// Machine-readable details for the error, if any.
+    pub fn data(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::InvalidPublicKeyLength { length } => {
+                Some(json!({ "expected": 65, "length": length }))
+            }
+            Error::ProofTypeNotAllowed { allowed } => Some(json!({ "allowed": allowed })),
+            Error::CircuitNotSupported { circuit } | Error::CircuitNotFound { circuit } => {
+                Some(json!({ "circuit": circuit }))
+            }
+            Error::Timeout {
+                stage,
+                timeout_secs,
+            } => Some(json!({ "stage": stage, "timeout_secs": timeout_secs })),
+            Error::Database { retryable, .. } | Error::Io { retryable, .. } => {
+                Some(json!({ "retryable": retryable }))
+            }
+            Error::Unhealthy(report) => serde_json::to_value(report).ok(),
+            Error::DependencyFailed { depends_on } => Some(json!({ "depends_on": depends_on })),
+            _ => None,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Database { retryable, .. } | Error::Io { retryable, .. } => *retryable,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidPublicKeyLength { .. } => write!(f, "Public key must be 65 bytes"),
+            Error::InvalidPublicKey(e) => write!(f, "Invalid public key: {}", e),
+            Error::DuplicateUuid => write!(f, "UUID already exists"),
+            Error::UuidNotFound => write!(f, "UUID not found"),
+            Error::DecryptionFailed => write!(f, "Failed to decrypt text"),
+            Error::InvalidProofRequest(e) => write!(f, "Failed to parse proof request: {}", e),
+            Error::ProofTypeNotAllowed { allowed } => {
+                write!(f, "This endpoint only allows {} inputs", allowed)
+            }
+            Error::CircuitNotSupported { circuit } => {
+                write!(f, "Could not find the given circuit name: {}", circuit)
+            }
+            Error::InvalidCancelToken => write!(f, "Invalid cancel token"),
+            Error::AlreadyCancelled => write!(f, "Request already cancelled"),
+            Error::PayloadConflict => {
+                write!(
+                    f,
+                    "A different request was already submitted with this UUID"
+                )
+            }
+            Error::InvalidIdentifier(e) => write!(f, "Invalid identifier: {}", e),
+            Error::InvalidDependency(e) => write!(f, "Invalid dependency: {}", e),
+
+            Error::Attestation(e) => write!(f, "Could not get attestation: {}", e),
+            Error::InvalidSharedSecret => write!(f, "Failed to store ephemeral key"),
+            Error::QueueClosed => write!(f, "Proving queue is closed"),
+            Error::Database { message, .. } => write!(f, "Database error: {}", message),
+            Error::Unhealthy(_) => write!(f, "Server is unhealthy"),
+            Error::Shutdown => write!(f, "Server is shutting down"),
+
+            Error::Io { message, .. } => write!(f, "{}", message),
+            Error::CircuitNotFound { circuit } => write!(f, "Circuit not found: {}", circuit),
+            Error::WitnessGenerationFailed(stderr) => {
+                write!(f, "Witness generation failed: {}", stderr)
+            }
+            Error::WitnessNotFound => write!(f, "Witness file does not exist"),
+            Error::ProofGenerationFailed(stderr) => {
+                write!(f, "Proof generation failed: {}", stderr)
+            }
+            Error::InvalidProofOutput(e) => write!(f, "{}", e),
+            Error::Timeout {
+                stage,
+                timeout_secs,
+            } => write!(f, "Timeout: {} exceeded {}s", stage, timeout_secs),
+            Error::Cancelled => write!(f, "Request cancelled"),
+            Error::Abandoned => write!(f, "Request was abandoned by the prover"),
+            Error::DependencyFailed { depends_on } => {
+                write!(f, "Request {} did not generate a proof", depends_on)
+            }
+        }
+    }
+}
+
+impl From<Error> for ErrorObjectOwned {
+    fn from(e: Error) -> Self {
+        ErrorObjectOwned::owned(e.code(), e.to_string(), e.data())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        let retryable = matches!(
+            e.kind(),
+            ErrorKind::Interrupted
+                | ErrorKind::WouldBlock
+                | ErrorKind::TimedOut
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::OutOfMemory
+        );
+
+        Error::Io {
+            message: e.to_string(),
+            retryable,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        let retryable = match &e {
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+            //connection exceptions, insufficient resources, operator intervention,
+            //serialization failures and deadlocks
+            sqlx::Error::Database(db_error) => db_error.code().is_some_and(|code| {
+                code.starts_with("08")
+                    || code.starts_with("53")
+                    || code.starts_with("57P")
+                    || code == "40001"
+                    || code == "40P01"
+            }),
+            _ => false,
+        };
+
+        Error::Database {
+            message: e.to_string(),
+            retryable,
+        }
+    }
+}
+
+impl<T> From<tokio::sync::mpsc::error::SendError<T>> for Error {
+    fn from(_: tokio::sync::mpsc::error::SendError<T>) -> Self {
+        Error::QueueClosed
+    }
+}
@@ -4,41 +4,79 @@ use crate::utils::get_tmp_folder_path;
 
 use crate::types::{ProofRequest, ProofType};
 use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
 
 pub struct FileGenerator {
     uuid: uuid::Uuid,
     pub proof_request: ProofRequest,
+    cancellation: CancellationToken,
+    span: Span,
 }
 
 impl FileGenerator {
-    pub fn new(uuid: uuid::Uuid, proof_request: ProofRequest) -> Self {
+    pub fn new(
+        uuid: uuid::Uuid,
+        proof_request: ProofRequest,
+        cancellation: CancellationToken,
+    ) -> Self {
+        //root span for everything the pipeline does for this request
+        let span = tracing::info_span!(
+            parent: None,
+            "request",
+            %uuid,
+            circuit = %proof_request.circuit().name,
+            proof_type = %ProofType::from(&proof_request),
+        );
+
         Self {
             uuid,
             proof_request,
+            cancellation,
+            span,
         }
     }
 
     pub fn uuid(&self) -> uuid::Uuid {
-        self.uuid.clone()
+        self.uuid
+    }
+
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
     }
 
-    pub fn proof_type(&self) -> ProofType {
-        (&self.proof_request).into()
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span.clone()
     }
 
     //create the tmp folder
     //create the inputs file
+    #[tracing::instrument(name = "file", skip_all)]
     pub async fn run(&self) -> Result<(uuid::Uuid, String), std::io::Error> {
         let path_str = get_tmp_folder_path(&self.uuid.to_string());
         let path = path::Path::new(&path_str);
-        tokio::fs::create_dir_all(path).await?;
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path)
+            .await?;
 
-        let mut input_file = tokio::fs::File::create(path.join("input.json")).await?;
+        let mut input_file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path.join("input.json"))
+            .await?;
 
         input_file
-            .write(self.proof_request.circuit().inputs.as_bytes())
+            .write_all(self.proof_request.circuit().inputs.as_bytes())
             .await?;
 
-        Ok((self.uuid.clone(), self.proof_request.circuit().name.clone()))
+        Ok((self.uuid, self.proof_request.circuit().name.clone()))
     }
 }
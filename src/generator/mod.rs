@@ -2,10 +2,93 @@ pub mod file_generator;
 pub mod proof_generator;
 pub mod witness_generator;
 
+use std::collections::HashMap;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
 use serde::Deserialize;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::Error;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Deserialize, Clone)]
 pub struct Circuit {
     pub name: String,
     pub inputs: String, //json
 }
+
+//the inputs are decrypted passport data and must never end up in the logs
+impl std::fmt::Debug for Circuit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Circuit")
+            .field("name", &self.name)
+            .field("inputs", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Per-circuit time limits for a single pipeline stage.
+#[derive(Clone)]
+pub struct Timeouts {
+    default: Duration,
+    circuits: HashMap<String, Duration>,
+}
+
+impl Timeouts {
+    pub fn new(default_secs: u64, circuits: Vec<(String, u64)>) -> Self {
+        Self {
+            default: Duration::from_secs(default_secs),
+            circuits: circuits
+                .into_iter()
+                .map(|(name, secs)| (name, Duration::from_secs(secs)))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, circuit_name: &str) -> Duration {
+        *self.circuits.get(circuit_name).unwrap_or(&self.default)
+    }
+}
+
+/// Runs `command` in its own process group so that a timeout or a cancellation
+/// kills everything it spawned, not just the direct child.
+pub async fn run_command(
+    mut command: Command,
+    stage: &'static str,
+    timeout: Duration,
+    cancellation: &CancellationToken,
+) -> Result<Output, Error> {
+    let child = command
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let pid = child.id();
+
+    tokio::select! {
+        output = child.wait_with_output() => Ok(output?),
+        _ = tokio::time::sleep(timeout) => {
+            kill_process_group(pid);
+            Err(Error::Timeout { stage, timeout_secs: timeout.as_secs() })
+        }
+        _ = cancellation.cancelled() => {
+            kill_process_group(pid);
+            Err(Error::Cancelled)
+        }
+    }
+}
+
+fn kill_process_group(pid: Option<u32>) {
+    if let Some(pid) = pid {
+        //the child was spawned with process_group(0), so its pid is also the group id
+        // SAFETY: killpg takes plain integers and touches no memory of this process. Linux
+        // does not hand out a pid that is still the id of a process group, so while any of
+        // the processes the child spawned is alive the signal can only reach them.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+}
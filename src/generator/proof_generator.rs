@@ -1,32 +1,67 @@
 use core::str;
 use std::path;
+use std::time::{Duration, Instant};
 
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
+
+use super::run_command;
+use crate::error::Error;
 use crate::utils::get_tmp_folder_path;
 
 pub struct ProofGenerator {
     uuid: uuid::Uuid,
+    circuit_name: String,
     zkey_file_path: String,
+    cancellation: CancellationToken,
+    span: Span,
 }
 
 impl ProofGenerator {
-    pub fn new(uuid: uuid::Uuid, zkey_file_path: String) -> Self {
+    pub fn new(
+        uuid: uuid::Uuid,
+        circuit_name: String,
+        zkey_file_path: String,
+        cancellation: CancellationToken,
+        span: Span,
+    ) -> Self {
         ProofGenerator {
             uuid,
+            circuit_name,
             zkey_file_path,
+            cancellation,
+            span,
         }
     }
 
     pub fn uuid(&self) -> uuid::Uuid {
-        self.uuid.clone()
+        self.uuid
+    }
+
+    pub fn circuit_name(&self) -> &str {
+        &self.circuit_name
+    }
+
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
     }
 
-    pub async fn run(&self, rapid_snark_path_exe: &String) -> Result<(), String> {
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    #[tracing::instrument(name = "proof", skip_all)]
+    pub async fn run(&self, rapid_snark_path_exe: &String, timeout: Duration) -> Result<(), Error> {
         // let witness_file_path_str = get_tmp_folder_path(&self.uuid.to_string());
         let tmp_folder_path = get_tmp_folder_path(&self.uuid.to_string());
         let witness_file_path = path::Path::new(&tmp_folder_path).join("output.wtns");
 
         if !witness_file_path.exists() {
-            return Err("Witness file does not exist".to_string());
+            return Err(Error::WitnessNotFound);
         }
 
         // let proof_file_path_str = get_tmp_folder_path(&self.uuid.to_string());
@@ -35,26 +70,37 @@ impl ProofGenerator {
         // let public_inputs = get_tmp_folder_path(&self.uuid);
         let public_inputs = path::Path::new(&tmp_folder_path).join("public_inputs.json");
 
-        match tokio::process::Command::new(format!("./{}", rapid_snark_path_exe))
+        let mut command = tokio::process::Command::new(format!("./{}", rapid_snark_path_exe));
+        command
             .arg(&self.zkey_file_path)
             .arg(witness_file_path)
             .arg(proof_file_path)
-            .arg(public_inputs.into_os_string().into_string().unwrap())
-            .output()
-            .await
-        {
+            .arg(public_inputs.into_os_string().into_string().unwrap());
+
+        let started = Instant::now();
+
+        match run_command(command, "proof", timeout, &self.cancellation).await {
             Ok(output) => {
-                if !output.status.success() || output.stderr.len() > 0 {
-                    return Err(str::from_utf8(&output.stderr)
-                        .unwrap_or("Proof failed")
-                        .to_string());
+                if !output.status.success() || !output.stderr.is_empty() {
+                    return Err(Error::ProofGenerationFailed(
+                        str::from_utf8(&output.stderr)
+                            .unwrap_or("Proof failed")
+                            .to_string(),
+                    ));
                 }
             }
             Err(err) => {
-                return Err(err.to_string());
+                if let Error::Timeout { .. } = err {
+                    metrics::counter!("proof_timeouts_total", "circuit" => self.circuit_name.clone())
+                        .increment(1);
+                }
+                return Err(err);
             }
         };
 
+        metrics::histogram!("proof_duration_seconds", "circuit" => self.circuit_name.clone())
+            .record(started.elapsed().as_secs_f64());
+
         Ok(())
     }
 }
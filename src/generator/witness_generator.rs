@@ -1,33 +1,68 @@
 use core::str;
 use std::path;
+use std::time::{Duration, Instant};
 
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
+
+use super::run_command;
+use crate::error::Error;
 use crate::utils::get_tmp_folder_path;
 
 pub struct WitnessGenerator {
     pub uuid: uuid::Uuid,
     circuit_file_name: String,
+    cancellation: CancellationToken,
+    span: Span,
 }
 
 impl WitnessGenerator {
-    pub fn new(uuid: uuid::Uuid, circuit_file_name: String) -> Self {
+    pub fn new(
+        uuid: uuid::Uuid,
+        circuit_file_name: String,
+        cancellation: CancellationToken,
+        span: Span,
+    ) -> Self {
         WitnessGenerator {
             uuid,
             circuit_file_name,
+            cancellation,
+            span,
         }
     }
 
+    pub fn circuit_name(&self) -> &str {
+        &self.circuit_file_name
+    }
+
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    #[tracing::instrument(name = "witness", skip_all)]
     pub async fn run(
         &self,
         circuit_folder: &str, //folder where all the circuit executables are
-    ) -> Result<(uuid::Uuid, String), String> {
+        timeout: Duration,
+    ) -> Result<(uuid::Uuid, String), Error> {
         let circuit_folder_path = path::Path::new(&circuit_folder);
         let path = circuit_folder_path
             .join(format!("{}_cpp", &self.circuit_file_name))
             .join(&self.circuit_file_name);
 
         if !path.exists() {
-            println!("{:?} does not exist", &path);
-            return Err(format!("Circuit not found: {}", path.to_str().unwrap()));
+            tracing::error!(path = %path.display(), "circuit executable does not exist");
+            return Err(Error::CircuitNotFound {
+                circuit: self.circuit_file_name.clone(),
+            });
         }
 
         let circuit_exe = format!("./{}", path.into_os_string().into_string().unwrap());
@@ -39,13 +74,13 @@ impl WitnessGenerator {
             .await
         {
             Ok(output) => {
-                if !output.status.success() || output.stderr.len() > 0 {
+                if !output.status.success() || !output.stderr.is_empty() {
                     let str = str::from_utf8(&output.stderr).unwrap();
-                    return Err(str.to_string());
+                    return Err(Error::WitnessGenerationFailed(str.to_string()));
                 }
             }
             Err(err) => {
-                return Err(err.to_string());
+                return Err(err.into());
             }
         }
 
@@ -53,23 +88,30 @@ impl WitnessGenerator {
         let input_file = tmp_folder_path.clone() + "/input.json";
         let output_file = tmp_folder_path + "/output.wtns";
 
-        let _ = match tokio::process::Command::new(circuit_exe)
-            .arg(&input_file)
-            .arg(&output_file)
-            .output()
-            .await
-        {
+        let mut command = tokio::process::Command::new(circuit_exe);
+        command.arg(&input_file).arg(&output_file);
+
+        let started = Instant::now();
+
+        match run_command(command, "witness", timeout, &self.cancellation).await {
             Ok(output) => {
-                if !output.status.success() || output.stderr.len() > 0 {
+                if !output.status.success() || !output.stderr.is_empty() {
                     let str = str::from_utf8(&output.stderr).unwrap();
-                    return Err(str.to_string());
+                    return Err(Error::WitnessGenerationFailed(str.to_string()));
                 }
             }
             Err(err) => {
-                return Err(err.to_string());
+                if let Error::Timeout { .. } = err {
+                    metrics::counter!("witness_timeouts_total", "circuit" => self.circuit_file_name.clone())
+                        .increment(1);
+                }
+                return Err(err);
             }
         };
 
-        Ok((self.uuid.clone(), self.circuit_file_name.clone()))
+        metrics::histogram!("witness_duration_seconds", "circuit" => self.circuit_file_name.clone())
+            .record(started.elapsed().as_secs_f64());
+
+        Ok((self.uuid, self.circuit_file_name.clone()))
     }
 }
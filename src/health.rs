@@ -0,0 +1,170 @@
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_nitro_enclaves_nsm_api::api::{Request, Response};
+use aws_nitro_enclaves_nsm_api::driver::nsm_process_request;
+use tokio::sync::mpsc::WeakSender;
+use tokio_util::sync::CancellationToken;
+
+use crate::db::ProofStore;
+use crate::generator::{
+    file_generator::FileGenerator, proof_generator::ProofGenerator,
+    witness_generator::WitnessGenerator,
+};
+use crate::store::JobStore;
+use crate::telemetry::queue_length;
+use crate::types::{ComponentHealth, DiskHealth, HealthResponse, JobHealth, QueueHealth};
+
+const DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks every component a proof request depends on.
+pub struct HealthCheck {
+    fd: i32,
+    db: Arc<dyn ProofStore>,
+    prover_path: PathBuf,
+    tmp_folder: PathBuf,
+    min_free_disk: u64,
+    max_job_age: Duration,
+    file_queue: WeakSender<FileGenerator>,
+    witness_queue: WeakSender<WitnessGenerator>,
+    proof_queue: WeakSender<ProofGenerator>,
+    jobs: Arc<JobStore>,
+    shutdown: CancellationToken,
+}
+
+impl HealthCheck {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fd: i32,
+        db: Arc<dyn ProofStore>,
+        prover_path: PathBuf,
+        tmp_folder: PathBuf,
+        min_free_disk_mb: u64,
+        max_job_age_secs: u64,
+        file_queue: WeakSender<FileGenerator>,
+        witness_queue: WeakSender<WitnessGenerator>,
+        proof_queue: WeakSender<ProofGenerator>,
+        jobs: Arc<JobStore>,
+        shutdown: CancellationToken,
+    ) -> Self {
+        Self {
+            fd,
+            db,
+            prover_path,
+            tmp_folder,
+            min_free_disk: min_free_disk_mb.saturating_mul(1024 * 1024),
+            max_job_age: Duration::from_secs(max_job_age_secs),
+            file_queue,
+            witness_queue,
+            proof_queue,
+            jobs,
+            shutdown,
+        }
+    }
+
+    pub async fn check(&self) -> HealthResponse {
+        let nsm = ComponentHealth::from(self.check_nsm());
+        let db = ComponentHealth::from(self.check_db().await);
+        let prover = ComponentHealth::from(self.check_prover());
+        let disk = self.check_disk();
+        let queues = self.check_queues();
+        let jobs = self.check_jobs().await;
+        //report unhealthy while draining so the load balancer stops sending requests
+        let shutting_down = self.shutdown.is_cancelled();
+
+        HealthResponse {
+            healthy: !shutting_down
+                && nsm.healthy
+                && db.healthy
+                && prover.healthy
+                && disk.healthy
+                && queues.healthy
+                && jobs.healthy,
+            nsm,
+            db,
+            prover,
+            disk,
+            queues,
+            jobs,
+            shutting_down,
+        }
+    }
+
+    fn check_nsm(&self) -> Result<(), String> {
+        if self.fd < 0 {
+            return Err("nsm device is not open".to_string());
+        }
+        match nsm_process_request(self.fd, Request::DescribeNSM) {
+            Response::DescribeNSM { .. } => Ok(()),
+            Response::Error(err) => Err(format!("{:?}", err)),
+            _ => Err("unexpected nsm response".to_string()),
+        }
+    }
+
+    async fn check_db(&self) -> Result<(), String> {
+        match tokio::time::timeout(DB_TIMEOUT, self.db.ping()).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no response within {}s", DB_TIMEOUT.as_secs())),
+        }
+    }
+
+    fn check_prover(&self) -> Result<(), String> {
+        use std::os::unix::fs::PermissionsExt;
+
+        match std::fs::metadata(&self.prover_path) {
+            Ok(metadata) if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 => {
+                Ok(())
+            }
+            Ok(_) => Err(format!(
+                "{} is not an executable file",
+                self.prover_path.display()
+            )),
+            Err(e) => Err(format!("{}: {}", self.prover_path.display(), e)),
+        }
+    }
+
+    fn check_disk(&self) -> DiskHealth {
+        let free_bytes = free_disk_space(&self.tmp_folder);
+        DiskHealth {
+            healthy: free_bytes.is_some_and(|free| free >= self.min_free_disk),
+            free_bytes,
+        }
+    }
+
+    //a queue that can no longer be upgraded means its loop has exited
+    fn check_queues(&self) -> QueueHealth {
+        let file = queue_length(&self.file_queue);
+        let witness = queue_length(&self.witness_queue);
+        let proof = queue_length(&self.proof_queue);
+        QueueHealth {
+            healthy: file.is_some() && witness.is_some() && proof.is_some(),
+            file,
+            witness,
+            proof,
+        }
+    }
+
+    async fn check_jobs(&self) -> JobHealth {
+        let oldest_age = self.jobs.oldest_job_age().await;
+        JobHealth {
+            healthy: oldest_age.is_none_or(|age| age <= self.max_job_age),
+            in_flight: self.jobs.in_flight().await,
+            oldest_age_secs: oldest_age.map(|age| age.as_secs()),
+        }
+    }
+}
+
+fn free_disk_space(path: &std::path::Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
@@ -1,56 +1,175 @@
 mod args;
+mod cache;
 mod db;
+mod dependencies;
+mod error;
 mod generator;
+mod health;
+mod remote_config;
+mod retention;
+mod retry;
 mod server;
+mod shutdown;
+mod signals;
 mod store;
+mod sweeper;
+mod telemetry;
+mod tls;
+mod transport;
 mod types;
 mod utils;
 
 use std::collections::HashMap;
 use std::path;
 use std::sync::Arc;
+use std::time::Duration;
 
+use args::LogFormat;
 use aws_nitro_enclaves_nsm_api::driver::{nsm_exit, nsm_init};
-use clap::Parser;
-use db::{set_witness_generated, update_proof};
-use generator::{proof_generator::ProofGenerator, witness_generator::WitnessGenerator};
-use jsonrpsee::server::Server;
+use clap::ValueEnum;
+use db::save_proof;
+use generator::{proof_generator::ProofGenerator, witness_generator::WitnessGenerator, Timeouts};
+use jsonrpsee::server::middleware::http::ProxyGetRequestLayer;
+use jsonrpsee::server::{serve_with_graceful_shutdown, stop_channel, RpcServiceBuilder, Server};
+use jsonrpsee::Methods;
+use retry::RetryPolicy;
 use server::RpcServer;
-use sqlx::postgres::PgPoolOptions;
-use utils::{cleanup, get_tmp_folder_path};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+use types::ProofType;
+use utils::{cleanup, discard_job};
 
 #[tokio::main]
 async fn main() {
-    let config = args::Config::parse();
-    let server_url = config.server_address;
+    let mut config = args::Config::load();
 
-    let server = Server::builder().build(server_url).await.unwrap();
+    if config.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    match config.log_format {
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(env_filter)
+            .init(),
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(env_filter).init(),
+    }
+
+    if let Some(address) = config.config_listen.clone() {
+        let remote = remote_config::receive(&address)
+            .await
+            .expect("could not receive the configuration");
+        if let Err(e) = config.apply_remote(remote) {
+            panic!("invalid configuration: {}", e);
+        }
+    }
 
-    let (file_generator_sender, mut file_generator_receiver) = tokio::sync::mpsc::channel(10);
-    let (witness_generator_sender, mut witness_generator_receiver) = tokio::sync::mpsc::channel(10);
-    let (proof_generator_sender, mut proof_generator_receiver) = tokio::sync::mpsc::channel(10);
+    utils::init_scratch_dir(&config.scratch_dir, config.scratch_tmpfs_size)
+        .expect("could not set up the scratch folder");
 
-    let server_addr = server.local_addr().unwrap();
+    if config.command == Some(args::Command::Migrate) {
+        let db = connect_database(&config).await;
+        let version = db.migrate().await.expect("could not migrate the database");
+        tracing::info!(version, "database migrated");
+        return;
+    }
+
+    if let Some(metrics_address) = &config.metrics_address {
+        let metrics_address = telemetry::install(metrics_address)
+            .await
+            .expect("could not start the metrics exporter");
+        tracing::info!("Metrics available on: http://{}", metrics_address);
+    }
+
+    //connections are accepted here rather than by jsonrpsee so the server can listen on vsock
+    let listener = transport::Listener::bind(&config.server_address)
+        .await
+        .unwrap();
+    let service_builder = Server::builder()
+        .set_http_middleware(
+            tower::ServiceBuilder::new()
+                .layer(ProxyGetRequestLayer::new("/health", "openpassport_health").unwrap()),
+        )
+        .set_rpc_middleware(RpcServiceBuilder::new().layer_fn(telemetry::RpcMetrics::new))
+        .to_service_builder();
+
+    let (file_generator_sender, mut file_generator_receiver) =
+        tokio::sync::mpsc::channel(config.file_queue_size);
+    let (witness_generator_sender, mut witness_generator_receiver) =
+        tokio::sync::mpsc::channel(config.witness_queue_size);
+    let (proof_generator_sender, mut proof_generator_receiver) =
+        tokio::sync::mpsc::channel(config.proof_queue_size);
+
+    tokio::spawn(telemetry::sample_queue_lengths(
+        file_generator_sender.downgrade(),
+        witness_generator_sender.downgrade(),
+        proof_generator_sender.downgrade(),
+    ));
+
+    let server_addr = listener.local_address().unwrap();
     let fd = nsm_init();
 
     // handle.stopped().await
 
-    println!("Server running on: http://{}", server_addr);
+    let tls = config
+        .tls
+        .then(|| tls::Tls::generate(fd).expect("could not set up tls"));
+    if let Some(tls) = &tls {
+        let public_key_hash: String = tls
+            .public_key_hash()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        tracing::info!(%public_key_hash, "tls enabled");
+    }
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    tracing::info!("Server running on: {}://{}", scheme, server_addr);
+
+    let db = connect_database(&config).await;
+    if config.auto_migrate {
+        db.migrate().await.expect("could not migrate the database");
+    } else {
+        db.check_schema()
+            .await
+            .expect("the database schema is not up to date");
+    }
 
-    let pool = match PgPoolOptions::new()
-        .max_connections(20)
-        .connect(&config.database_url)
+    //nothing holds the requests the previous run left blocked
+    let abandoned = db
+        .fail_blocked_proofs(Duration::ZERO)
         .await
-    {
-        Ok(pool) => pool,
-        Err(e) => {
-            panic!("Error: {:?}", e);
-        }
-    };
+        .expect("could not fail the blocked requests");
+    if !abandoned.is_empty() {
+        tracing::warn!(
+            failed = abandoned.len(),
+            "failed blocked requests of the previous run"
+        );
+    }
+
+    //rows of proof types this server no longer accepts are expired too
+    let retention_policies: Vec<retention::Policy> = ProofType::value_variants()
+        .iter()
+        .map(|proof_type| retention::Policy {
+            proof_type: *proof_type,
+            redact_after: config.redact_after(*proof_type).map(Duration::from_secs),
+            delete_after: config.delete_after(*proof_type).map(Duration::from_secs),
+        })
+        .filter(|policy| policy.redact_after.is_some() || policy.delete_after.is_some())
+        .collect();
 
     let circuit_folder = config.circuit_folder;
     let zkey_folder = config.zkey_folder;
 
+    let witness_timeouts = Timeouts::new(config.witness_timeout, config.circuit_witness_timeout);
+    let proof_timeouts = Timeouts::new(config.proof_timeout, config.circuit_proof_timeout);
+
+    let file_retry = RetryPolicy::new(config.file_retries, config.file_retry_backoff);
+    let witness_retry = RetryPolicy::new(config.witness_retries, config.witness_retry_backoff);
+    let proof_retry = RetryPolicy::new(config.proof_retries, config.proof_retry_backoff);
+
     let mut circuit_zkey_map = HashMap::new();
 
     let entries = std::fs::read_dir(std::path::Path::new(&circuit_folder)).unwrap();
@@ -72,18 +191,21 @@ async fn main() {
         circuit_zkey_map.insert(circuit_name, zkey_path_str.to_string());
     }
 
-    let circuit_zkey_map_arc = Arc::new(circuit_zkey_map);
-
-    let handle = server.start(
-        server::RpcServerImpl::new(
+    let proof_cache = (config.proof_cache_ttl > 0).then(|| {
+        let proof_cache = cache::ProofCache::new(
             fd,
-            store::LruStore::new(1000),
-            file_generator_sender,
-            Arc::clone(&circuit_zkey_map_arc),
-            pool.clone(),
+            Duration::from_secs(config.proof_cache_ttl),
+            &circuit_zkey_map,
         )
-        .into_rpc(),
-    );
+        .expect("could not hash the zkeys for the proof cache");
+        Arc::new(proof_cache)
+    });
+
+    let public_signals = signals::PublicSignals::load(&circuit_folder, circuit_zkey_map.keys())
+        .expect("could not read the circuit symbol files");
+
+    let circuit_zkey_map_arc = Arc::new(circuit_zkey_map);
+    let jobs = Arc::new(store::JobStore::default());
 
     let rapid_snark_path_exe = path::Path::new(&config.rapidsnark_path)
         .join("package")
@@ -93,38 +215,148 @@ async fn main() {
     if !rapid_snark_path_exe.exists() {
         panic!("rapid snark path does not exist!");
     }
-    let rapid_snark_path = rapid_snark_path_exe.into_os_string().into_string().unwrap();
+    let rapid_snark_path = rapid_snark_path_exe.to_str().unwrap().to_string();
+
+    let dependencies = Arc::new(dependencies::Dependencies::default());
+    tokio::spawn(dependencies::run(
+        Arc::clone(&db),
+        Arc::clone(&jobs),
+        Arc::clone(&dependencies),
+        file_generator_sender.clone(),
+    ));
+
+    tokio::spawn(sweeper::run(
+        Arc::clone(&db),
+        Arc::clone(&jobs),
+        Duration::from_secs(config.sweep_interval),
+        Duration::from_secs(config.stuck_request_timeout),
+        Duration::from_secs(config.max_blocked_age),
+        config.proof_types.clone(),
+    ));
+
+    if !retention_policies.is_empty() {
+        tokio::spawn(retention::run(
+            Arc::clone(&db),
+            Duration::from_secs(config.retention_interval),
+            retention_policies,
+        ));
+    }
+
+    let shutdown = CancellationToken::new();
+    let health = health::HealthCheck::new(
+        fd,
+        Arc::clone(&db),
+        rapid_snark_path_exe,
+        utils::get_scratch_dir().to_path_buf(),
+        config.health_min_free_disk,
+        config.health_max_job_age,
+        file_generator_sender.downgrade(),
+        witness_generator_sender.downgrade(),
+        proof_generator_sender.downgrade(),
+        Arc::clone(&jobs),
+        shutdown.clone(),
+    );
 
+    let methods: Methods = server::RpcServerImpl::new(
+        fd,
+        store::LruStore::new(config.ecdh_store_capacity),
+        file_generator_sender,
+        Arc::clone(&circuit_zkey_map_arc),
+        Arc::clone(&db),
+        Arc::clone(&jobs),
+        dependencies,
+        health,
+        shutdown.clone(),
+        config.proof_types.clone(),
+        tls.as_ref().map(|tls| tls.public_key_hash().to_vec()),
+        proof_cache.clone(),
+        config.max_batch_size,
+    )
+    .into_rpc()
+    .into();
+
+    let (stop_handle, handle) = stop_channel();
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "could not accept connection");
+                        tokio::time::sleep(transport::ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                },
+                _ = stop_handle.clone().shutdown() => break,
+            };
+            let service = service_builder
+                .clone()
+                .build(methods.clone(), stop_handle.clone());
+            let stopped = stop_handle.clone().shutdown();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let served = match tls {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => serve_with_graceful_shutdown(stream, service, stopped).await,
+                        Err(e) => Err(e.into()),
+                    },
+                    None => serve_with_graceful_shutdown(stream, service, stopped).await,
+                };
+                if let Err(e) = served {
+                    tracing::debug!(error = %e, "connection closed");
+                }
+            });
+        }
+    });
+
+    let server_handle = handle.clone();
     tokio::select! {
         _ = handle.stopped() => {
-            println!("Server stopped");
+            tracing::info!("Server stopped");
             nsm_exit(fd);
         }
 
+    //the pipeline loops keep running while this branch drains them
+    _ = async {
+        shutdown::wait_for_signal().await;
+        shutdown::drain(&shutdown, &jobs, &*db, Duration::from_secs(config.shutdown_timeout)).await;
+    } => {
+        let _ = server_handle.stop();
+        nsm_exit(fd);
+        tracing::info!("Server shut down");
+    }
+
     _ = async {
         while let Some(file_generator) = file_generator_receiver.recv().await {
             let uuid = file_generator.uuid();
 
-            let pool_clone = pool.clone();
+            if file_generator.is_cancelled() {
+                discard_job(uuid, &jobs).instrument(file_generator.span()).await;
+                continue;
+            }
+
+            let span = file_generator.span();
+            let db_clone = Arc::clone(&db);
+            let jobs_clone = Arc::clone(&jobs);
             let witness_generator_clone = witness_generator_sender.clone();
+            let file_retry = file_retry.clone();
             tokio::spawn(async move {
-                let (uuid, circuit_name) = match file_generator.run().await {
+                let (uuid, circuit_name) = match file_retry.run(uuid, &*db_clone, &file_generator.cancellation(), || file_generator.run()).await {
                     Ok((uuid, circuit_name)) => (uuid, circuit_name),
                     Err(e) => {
-                        dbg!(&e);
-                        cleanup(uuid.clone(), &pool_clone, e.to_string()).await;
+                        cleanup(uuid, &*db_clone, &jobs_clone, &e).await;
                         return;
                     }
                 };
-                if let Err(e) = witness_generator_clone.send(WitnessGenerator::new(
-                    uuid.clone(),
-                    circuit_name
-                )).await {
-                    dbg!(&e);
-                    cleanup(uuid, &pool_clone, e.to_string()).await;
-                    return;
+                if let Err(e) = file_retry.run(uuid, &*db_clone, &file_generator.cancellation(), || witness_generator_clone.send(WitnessGenerator::new(
+                    uuid,
+                    circuit_name.clone(),
+                    file_generator.cancellation(),
+                    file_generator.span(),
+                ))).await {
+                    cleanup(uuid, &*db_clone, &jobs_clone, &e).await;
                 }
-            });
+            }.instrument(span));
         }
     } => {}
 
@@ -136,39 +368,48 @@ async fn main() {
             let circuit_folder = circuit_folder.clone();
             let zkey_folder = zkey_folder.clone();
 
-            let uuid = witness_generator.uuid.clone();
+            let uuid = witness_generator.uuid;
+
+            if witness_generator.is_cancelled() {
+                discard_job(uuid, &jobs).instrument(witness_generator.span()).await;
+                continue;
+            }
+
+            let timeout = witness_timeouts.get(witness_generator.circuit_name());
+            let span = witness_generator.span();
 
-            let pool_clone = pool.clone();
+            let db_clone = Arc::clone(&db);
+            let jobs_clone = Arc::clone(&jobs);
+            let witness_retry = witness_retry.clone();
             tokio::spawn(async move {
-                match witness_generator
-                    .run(&circuit_folder)
+                match witness_retry.run(uuid, &*db_clone, &witness_generator.cancellation(), || witness_generator
+                    .run(&circuit_folder, timeout))
                     .await {
                     Ok((uuid, circuit_name)) => {
                         let zkey_file = circuit_zkey_map_arc_clone.get(circuit_name.as_str()).unwrap();
                         let zkey_file_path = path::Path::new(&zkey_folder).join(zkey_file).to_str().unwrap().to_string();
 
-                        if let Err(e) = set_witness_generated(uuid.clone(), &pool_clone).await {
-                            dbg!(&e);
-                            cleanup(uuid.clone(), &pool_clone, e.to_string()).await;
+                        if let Err(e) = witness_retry.run(uuid, &*db_clone, &witness_generator.cancellation(), || db_clone.set_witness_generated(uuid)).await {
+                            cleanup(uuid, &*db_clone, &jobs_clone, &e).await;
                             return;
                         }
+                        tracing::info!("witness generated");
 
-                        if let Err(e) = proof_generator_sender_clone.send(ProofGenerator::new(
-                            uuid.clone(),
-                            zkey_file_path,
-                        )).await {
-                            dbg!(&e);
-                            cleanup(uuid.clone(), &pool_clone, e.to_string()).await;
-                            return;
+                        if let Err(e) = witness_retry.run(uuid, &*db_clone, &witness_generator.cancellation(), || proof_generator_sender_clone.send(ProofGenerator::new(
+                            uuid,
+                            circuit_name.clone(),
+                            zkey_file_path.clone(),
+                            witness_generator.cancellation(),
+                            witness_generator.span(),
+                        ))).await {
+                            cleanup(uuid, &*db_clone, &jobs_clone, &e).await;
                         }
                     },
                     Err(e) => {
-                        dbg!(&e);
-                        cleanup(uuid.clone(), &pool_clone, e.to_string()).await;
-                        return;
+                        cleanup(uuid, &*db_clone, &jobs_clone, &e).await;
                     }
                 }
-            });
+            }.instrument(span));
         }
     } => {}
 
@@ -176,19 +417,57 @@ async fn main() {
         while let Some(proof_generator) = proof_generator_receiver.recv().await {
             let uuid = proof_generator.uuid();
 
-            if let Err(e) = proof_generator.run(&rapid_snark_path).await {
-                dbg!(&e);
-                cleanup(uuid.clone(), &pool, e.to_string()).await;
+            if proof_generator.is_cancelled() {
+                discard_job(uuid, &jobs).instrument(proof_generator.span()).await;
                 continue;
             }
-            if let Err(e) = update_proof(uuid.clone(), &pool).await {
-                dbg!(&e);
-                cleanup(uuid.clone(), &pool, e.to_string()).await;
-                continue;
+
+            let timeout = proof_timeouts.get(proof_generator.circuit_name());
+
+            async {
+                if let Err(e) = proof_retry.run(uuid, &*db, &proof_generator.cancellation(), || proof_generator.run(&rapid_snark_path, timeout)).await {
+                    cleanup(uuid, &*db, &jobs, &e).await;
+                    return;
+                }
+                if let Err(e) = proof_retry.run(uuid, &*db, &proof_generator.cancellation(), || save_proof(uuid, proof_generator.circuit_name(), &public_signals, proof_cache.as_deref(), &*db)).await {
+                    cleanup(uuid, &*db, &jobs, &e).await;
+                    return;
+                }
+                tracing::info!("proof generated");
+                metrics::counter!("proofs_generated_total", "circuit" => proof_generator.circuit_name().to_string()).increment(1);
+                discard_job(uuid, &jobs).await;
             }
-            let tmp_folder = get_tmp_folder_path(&uuid.to_string());
-            let _ = tokio::fs::remove_dir_all(tmp_folder).await;
+            .instrument(proof_generator.span())
+            .await;
         }
     } => {}
     }
 }
+
+async fn connect_database(config: &args::Config) -> Arc<dyn db::ProofStore> {
+    let database_tls = if db::is_postgres(&config.database_url) {
+        db::tls::DatabaseTls::new(
+            config.database_ca_file.as_deref(),
+            config.database_cert_fingerprint.as_deref(),
+            config.database_insecure,
+        )
+        .expect("invalid database tls settings")
+    } else {
+        None
+    };
+
+    match db::connect(
+        &config.database_url,
+        config.database_address.as_ref(),
+        database_tls,
+        config.db_max_connections,
+        &config.instance_id,
+    )
+    .await
+    {
+        Ok(db) => db,
+        Err(e) => {
+            panic!("Error: {:?}", e);
+        }
+    }
+}
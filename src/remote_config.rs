@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+
+use crate::args::redact_url;
+use crate::transport::{Address, Listener, Stream};
+use crate::types::ProofType;
+
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings the parent instance sends to the enclave at startup, so that secrets never
+/// have to be passed on the command line.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    pub database_url: String,
+    #[serde(default)]
+    pub proof_types: Option<Vec<ProofType>>,
+}
+
+impl std::fmt::Debug for RemoteConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteConfig")
+            .field("database_url", &redact_url(&self.database_url))
+            .field("proof_types", &self.proof_types)
+            .finish()
+    }
+}
+
+/// Waits for the first valid configuration message on `address`. Invalid messages are
+/// logged and the next connection is awaited.
+pub async fn receive(address: &Address) -> std::io::Result<RemoteConfig> {
+    let listener = Listener::bind(address).await?;
+    tracing::info!(%address, "waiting for configuration");
+
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        match read_message(&mut stream).await {
+            Ok(config) => {
+                tracing::info!(%peer, ?config, "received configuration");
+                return Ok(config);
+            }
+            Err(e) => tracing::warn!(%peer, error = %e, "ignoring invalid configuration"),
+        }
+    }
+}
+
+async fn read_message(stream: &mut Stream) -> Result<RemoteConfig, String> {
+    let mut message = Vec::new();
+    match tokio::time::timeout(
+        READ_TIMEOUT,
+        stream.take(MAX_MESSAGE_SIZE + 1).read_to_end(&mut message),
+    )
+    .await
+    {
+        Ok(Ok(_)) => (),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err(format!("no message within {}s", READ_TIMEOUT.as_secs())),
+    }
+
+    if message.len() as u64 > MAX_MESSAGE_SIZE {
+        return Err(format!("message exceeds {} bytes", MAX_MESSAGE_SIZE));
+    }
+
+    let message = std::str::from_utf8(&message)
+        .map_err(|e| e.to_string())?
+        .trim();
+
+    //older parents send the database url on its own
+    if message.starts_with("postgres://") || message.starts_with("postgresql://") {
+        if message.lines().count() > 1 {
+            return Err("expected a single database url".to_string());
+        }
+        return Ok(RemoteConfig {
+            database_url: message.to_string(),
+            proof_types: None,
+        });
+    }
+
+    serde_json::from_str(message).map_err(|e| e.to_string())
+}
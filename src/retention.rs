@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::ProofStore;
+use crate::types::ProofType;
+
+/// How long the requests of a proof type are kept.
+pub struct Policy {
+    pub proof_type: ProofType,
+    /// Clears the proof and request details once the request has finished for this long.
+    pub redact_after: Option<Duration>,
+    /// Deletes the request once it has been created for this long.
+    pub delete_after: Option<Duration>,
+}
+
+/// Applies `policies` every `interval`, the first run happens immediately. Requests that
+/// are still in the pipeline are never touched.
+pub async fn run(db: Arc<dyn ProofStore>, interval: Duration, policies: Vec<Policy>) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        for policy in &policies {
+            apply(&*db, policy).await;
+        }
+    }
+}
+
+async fn apply(db: &dyn ProofStore, policy: &Policy) {
+    let proof_type = policy.proof_type.to_string();
+
+    if let Some(redact_after) = policy.redact_after {
+        if let Ok(redacted) = db.redact_proofs(policy.proof_type, redact_after).await {
+            if redacted > 0 {
+                tracing::info!(redacted, proof_type, "redacted expired requests");
+                metrics::counter!("retention_redacted_total", "proof_type" => proof_type.clone())
+                    .increment(redacted);
+            }
+        }
+    }
+
+    if let Some(delete_after) = policy.delete_after {
+        if let Ok(deleted) = db.delete_proofs(policy.proof_type, delete_after).await {
+            if deleted > 0 {
+                tracing::info!(deleted, proof_type, "deleted expired requests");
+                metrics::counter!("retention_deleted_total", "proof_type" => proof_type)
+                    .increment(deleted);
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::db::ProofStore;
+use crate::error::Error;
+
+/// How often a failing pipeline stage is retried before the request is failed.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, backoff_ms: u64) -> Self {
+        Self {
+            retries,
+            backoff: Duration::from_millis(backoff_ms),
+        }
+    }
+
+    /// Runs `op` until it succeeds, fails with a permanent error or runs out of retries.
+    /// The backoff doubles after every attempt and is cut short by `cancellation`, e.g.
+    /// when the server shuts down.
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        uuid: uuid::Uuid,
+        db: &dyn ProofStore,
+        cancellation: &CancellationToken,
+        mut op: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<Error>,
+    {
+        let mut retry = 0;
+        loop {
+            let e: Error = match op().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e.into(),
+            };
+
+            if !e.is_retryable() || retry >= self.retries {
+                return Err(e);
+            }
+
+            tracing::warn!(
+                retry = retry + 1,
+                code = e.code(),
+                error = %e,
+                "retrying after transient failure"
+            );
+            let _ = db.increment_attempts(uuid).await;
+            let backoff = self.backoff.saturating_mul(2u32.saturating_pow(retry));
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = cancellation.cancelled() => return Err(Error::Cancelled),
+            }
+            retry += 1;
+        }
+    }
+}
@@ -1,20 +1,28 @@
 use aws_nitro_enclaves_nsm_api::api::ErrorCode;
 use jsonrpsee::core::async_trait;
 use jsonrpsee::proc_macros::rpc;
-use jsonrpsee::types;
-use jsonrpsee::{types::ErrorObjectOwned, ResponsePayload};
+use jsonrpsee::ResponsePayload;
 use p256::ecdh::EphemeralSecret;
 use p256::elliptic_curve::PublicKey;
 use rand_core::{CryptoRng, RngCore};
-use sqlx::Pool;
 use std::collections::HashMap;
 use std::io;
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use crate::db::create_proof_status;
-use crate::store::LruStore;
-use crate::types::{ProofRequest, SubmitRequest};
-use crate::utils::{self, nsm_get_random};
+use crate::cache::ProofCache;
+use crate::db::types::Status;
+use crate::db::{self, NewProof, Proof, ProofStore};
+use crate::dependencies::Dependencies;
+use crate::error::Error;
+use crate::health::HealthCheck;
+use crate::store::{JobStore, LruStore};
+use crate::types::{
+    BatchStatus, HealthResponse, ProofPage, ProofRequest, ProofSummary, ProofType, SubmitPayload,
+    SubmitRequest, SubmitResponse,
+};
+use crate::utils::{self, get_tmp_folder_path, nsm_get_random, remove_tmp_folder};
 use crate::{generator::file_generator::FileGenerator, types::HelloResponse};
 
 #[rpc(server, namespace = "openpassport")]
@@ -32,7 +40,80 @@ pub trait Rpc {
         nonce: Vec<u8>,
         cipher_text: Vec<u8>,
         auth_tag: Vec<u8>,
+    ) -> ResponsePayload<'static, SubmitResponse>;
+    #[method(name = "cancel")]
+    async fn cancel(
+        &self,
+        uuid: uuid::Uuid,
+        cancel_token: Vec<u8>,
     ) -> ResponsePayload<'static, String>;
+    #[method(name = "health")]
+    async fn health(&self) -> ResponsePayload<'static, HealthResponse>;
+    #[method(name = "proofs_by_identifier")]
+    async fn proofs_by_identifier(
+        &self,
+        identifier: String,
+        identifier_key: String,
+        before: Option<uuid::Uuid>,
+        limit: Option<u32>,
+    ) -> ResponsePayload<'static, ProofPage>;
+    #[method(name = "batch_status")]
+    async fn batch_status(&self, batch_id: uuid::Uuid) -> ResponsePayload<'static, BatchStatus>;
+}
+
+//per page, the most recent ones first
+const MAX_PROOFS_PER_IDENTIFIER: u32 = 100;
+
+//the proof, public inputs and named public signals of an earlier request
+type CachedProof = (Proof, Vec<String>, Option<serde_json::Value>);
+
+//a request of the payload on its way into the pipeline
+struct Item {
+    uuid: uuid::Uuid,
+    submit_request: SubmitRequest,
+    cache_key: Option<Vec<u8>>,
+    cached: Option<CachedProof>,
+    identifier_key_hash: Option<Vec<u8>>,
+    //held back until the request it depends on has generated its proof
+    blocked: bool,
+}
+
+impl Item {
+    fn new_proof<'a>(&'a self, payload_digest: &'a [u8]) -> NewProof<'a> {
+        let proof_request = &self.submit_request.proof_request_type;
+        let (endpoint_type, endpoint) = match proof_request {
+            ProofRequest::Disclose {
+                endpoint_type,
+                endpoint,
+                ..
+            } => (Some(*endpoint_type), Some(endpoint.as_str())),
+            _ => (None, None),
+        };
+
+        NewProof {
+            uuid: self.uuid,
+            proof_type: ProofType::from(proof_request),
+            circuit_name: &proof_request.circuit().name,
+            onchain: self.submit_request.onchain,
+            endpoint_type,
+            endpoint,
+            identifier: proof_request.identifier(),
+            identifier_key_hash: self.identifier_key_hash.as_deref(),
+            payload_digest,
+            //copies are not cached again, so a proof expires with the original
+            cache_key: self.cache_key.as_deref().filter(|_| self.cached.is_none()),
+            depends_on: self.submit_request.depends_on,
+            blocked: self.blocked,
+            cached: self
+                .cached
+                .as_ref()
+                .map(|(proof, public_inputs, public_signals)| db::CachedProof {
+                    proof,
+                    public_inputs,
+                    public_signals: public_signals.as_ref(),
+                }),
+        }
+    }
 }
 
 pub struct RpcServerImpl {
@@ -40,16 +121,33 @@ pub struct RpcServerImpl {
     store: LruStore,
     file_generator_sender: tokio::sync::mpsc::Sender<FileGenerator>,
     circuit_zkey_map: Arc<HashMap<String, String>>,
-    db: Pool<sqlx::Postgres>,
+    db: Arc<dyn ProofStore>,
+    jobs: Arc<JobStore>,
+    dependencies: Arc<Dependencies>,
+    health: HealthCheck,
+    shutdown: CancellationToken,
+    proof_types: Vec<ProofType>,
+    tls_public_key_hash: Option<Vec<u8>>,
+    cache: Option<Arc<ProofCache>>,
+    max_batch_size: usize,
 }
 
 impl RpcServerImpl {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         fd: i32,
         store: LruStore,
         file_generator_sender: tokio::sync::mpsc::Sender<FileGenerator>,
         circuit_zkey_map: Arc<HashMap<String, String>>,
-        db: Pool<sqlx::Postgres>,
+        db: Arc<dyn ProofStore>,
+        jobs: Arc<JobStore>,
+        dependencies: Arc<Dependencies>,
+        health: HealthCheck,
+        shutdown: CancellationToken,
+        proof_types: Vec<ProofType>,
+        tls_public_key_hash: Option<Vec<u8>>,
+        cache: Option<Arc<ProofCache>>,
+        max_batch_size: usize,
     ) -> Self {
         Self {
             fd,
@@ -57,23 +155,258 @@ impl RpcServerImpl {
             file_generator_sender,
             circuit_zkey_map,
             db,
+            jobs,
+            dependencies,
+            health,
+            shutdown,
+            proof_types,
+            tls_public_key_hash,
+            cache,
+            max_batch_size,
+        }
+    }
+
+    /// The answer to a request or batch that was already accepted under `uuid` with the
+    /// same payload, so that a retried `submit_request` gets the original answer. A
+    /// different payload is a conflict.
+    async fn previous_submission(
+        &self,
+        uuid: uuid::Uuid,
+        payload_digest: &[u8],
+    ) -> Result<Option<SubmitResponse>, Error> {
+        let response = match self.db.get(uuid).await? {
+            Some(record) if record.payload_digest.as_deref() == Some(payload_digest) => {
+                SubmitResponse::Single(uuid)
+            }
+            Some(_) => return Err(Error::PayloadConflict),
+            None => {
+                let batch = self.db.find_batch(uuid).await?;
+                match batch.first() {
+                    Some(record) if record.payload_digest.as_deref() == Some(payload_digest) => {
+                        SubmitResponse::Batch {
+                            batch_id: uuid,
+                            uuids: batch.iter().map(|record| record.request_id).collect(),
+                        }
+                    }
+                    Some(_) => return Err(Error::PayloadConflict),
+                    None => return Ok(None),
+                }
+            }
+        };
+        tracing::info!("repeated request");
+        metrics::counter!("repeated_submissions_total").increment(1);
+        Ok(Some(response))
+    }
+
+    /// Whether the payload was encrypted with the shared secret of the request accepted
+    /// under `uuid`, i.e. comes from the client that submitted it.
+    async fn holds_accepted_secret(
+        &self,
+        uuid: uuid::Uuid,
+        nonce: &[u8],
+        cipher_text: Vec<u8>,
+        auth_tag: &[u8],
+    ) -> bool {
+        let Some(key) = self.store.get_accepted_secret(&uuid).await else {
+            return false;
+        };
+        let Ok(key) = <[u8; 32]>::try_from(key) else {
+            return false;
+        };
+        utils::decrypt(key, cipher_text, auth_tag, nonce).is_ok()
+    }
+
+    /// Rejects requests that this server does not prove.
+    fn check_request(&self, submit_request: &SubmitRequest) -> Result<(), Error> {
+        let proof_type = ProofType::from(&submit_request.proof_request_type);
+        if !self.proof_types.contains(&proof_type) {
+            let allowed: Vec<String> = self.proof_types.iter().map(ToString::to_string).collect();
+            return Err(Error::ProofTypeNotAllowed {
+                allowed: allowed.join(", "),
+            });
+        }
+
+        let circuit_name = &submit_request.proof_request_type.circuit().name;
+        if !self.circuit_zkey_map.contains_key(circuit_name) {
+            return Err(Error::CircuitNotSupported {
+                circuit: circuit_name.clone(),
+            });
+        }
+
+        let proof_request = &submit_request.proof_request_type;
+        match (proof_request.identifier(), proof_request.identifier_key()) {
+            (Some(identifier), Some(identifier_key)) => {
+                utils::check_identifier(identifier)?;
+                utils::check_identifier_key(identifier_key)?;
+            }
+            (Some(_), None) => {
+                return Err(Error::InvalidIdentifier(
+                    "an identifier needs an identifierKey".to_string(),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(Error::InvalidIdentifier(
+                    "an identifierKey needs an identifier".to_string(),
+                ))
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    /// Whether a request depending on `depends_on` has to wait for it. Requests that
+    /// failed, were cancelled or do not exist cannot be depended on, and are told apart
+    /// by nobody.
+    async fn is_blocked(&self, depends_on: uuid::Uuid) -> Result<bool, Error> {
+        match self.db.get(depends_on).await? {
+            Some(record) if record.status == Status::ProofGenerated => Ok(false),
+            Some(record) if record.status.is_in_progress() => Ok(true),
+            _ => Err(Error::DependencyFailed { depends_on }),
+        }
+    }
+
+    /// Resolves the dependency of the request at the end of `earlier`, the requests of
+    /// its batch that come before it. A `dependsOnIndex` is replaced by the UUID of the
+    /// request it refers to, which blocks it unless that request was served from the
+    /// cache.
+    async fn resolve_dependency(
+        &self,
+        submit_request: &mut SubmitRequest,
+        earlier: &[Item],
+    ) -> Result<bool, Error> {
+        match (submit_request.depends_on, submit_request.depends_on_index) {
+            (Some(_), Some(_)) => Err(Error::InvalidDependency(
+                "dependsOn and dependsOnIndex cannot both be set".to_string(),
+            )),
+            (Some(depends_on), None) => self.is_blocked(depends_on).await,
+            (None, Some(index)) => match earlier.get(index) {
+                Some(item) => {
+                    submit_request.depends_on = Some(item.uuid);
+                    Ok(item.cached.is_none())
+                }
+                None => Err(Error::InvalidDependency(format!(
+                    "dependsOnIndex {} does not refer to an earlier request of the batch",
+                    index
+                ))),
+            },
+            (None, None) => Ok(false),
+        }
+    }
+
+    //a failed lookup only costs a proof, the request goes through the pipeline
+    async fn find_cached(&self, cache_key: Option<&[u8]>) -> Option<CachedProof> {
+        let cache = self.cache.as_ref()?;
+        let cache_key = cache_key?;
+        let record = self.db.find_cached(cache_key, cache.ttl()).await.ok()??;
+        let (proof, public_inputs) = (record.proof?, record.public_inputs?);
+        let sealed = record.cache_seal.is_some_and(|seal| {
+            cache.verify(
+                cache_key,
+                &proof,
+                &public_inputs,
+                record.public_signals.as_ref(),
+                &seal,
+            )
+        });
+        if !sealed {
+            tracing::warn!(request_id = %record.request_id, "cached proof is not sealed by this enclave");
+            metrics::counter!("proof_cache_rejected_total").increment(1);
+            return None;
         }
+        Some((proof, public_inputs, record.public_signals))
+    }
+
+    /// Registers the jobs of the requests that go through the pipeline, all or none, in
+    /// the order of `items`. Requests served from the cache get no job.
+    async fn insert_jobs(
+        &self,
+        items: &[Item],
+        cancel_token: &[u8],
+    ) -> Result<Vec<Option<CancellationToken>>, Error> {
+        let mut cancellations = Vec::with_capacity(items.len());
+        for item in items {
+            if item.cached.is_some() {
+                cancellations.push(None);
+                continue;
+            }
+            match self
+                .jobs
+                .insert_job(item.uuid, cancel_token.to_vec(), item.blocked)
+                .await
+            {
+                Ok(cancellation) => cancellations.push(Some(cancellation)),
+                Err(e) => {
+                    self.remove_jobs(&items[..cancellations.len()]).await;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(cancellations)
+    }
+
+    async fn remove_jobs(&self, items: &[Item]) {
+        for item in items.iter().filter(|item| item.cached.is_none()) {
+            self.jobs.remove_job(&item.uuid).await;
+        }
+    }
+
+    /// Sends a recorded request down the pipeline, or holds it back until the request it
+    /// depends on has generated its proof.
+    async fn start(&self, item: Item, cancellation: Option<CancellationToken>) {
+        let circuit_name = item
+            .submit_request
+            .proof_request_type
+            .circuit()
+            .name
+            .clone();
+        let Some(cancellation) = cancellation else {
+            tracing::info!(request_id = %item.uuid, "proof served from cache");
+            metrics::counter!("proof_cache_hits_total", "circuit" => circuit_name).increment(1);
+            return;
+        };
+        if item.cache_key.is_some() {
+            metrics::counter!("proof_cache_misses_total", "circuit" => circuit_name).increment(1);
+        }
+
+        let file_generator = FileGenerator::new(
+            item.uuid,
+            item.submit_request.proof_request_type,
+            cancellation,
+        );
+        if let Some(depends_on) = item.submit_request.depends_on.filter(|_| item.blocked) {
+            self.dependencies.hold(depends_on, file_generator).await;
+            tracing::info!(request_id = %item.uuid, %depends_on, "request accepted, blocked");
+            return;
+        }
+        //the request is recorded already, so it fails like any other in the pipeline
+        if let Err(e) = self.file_generator_sender.send(file_generator).await {
+            let span = e.0.span();
+            utils::cleanup(item.uuid, &*self.db, &self.jobs, &Error::from(e))
+                .instrument(span)
+                .await;
+            return;
+        }
+
+        tracing::info!(request_id = %item.uuid, "request accepted");
     }
 }
 
 #[async_trait]
 impl RpcServer for RpcServerImpl {
+    #[tracing::instrument(skip_all, fields(%uuid))]
     async fn hello(
         &self,
         user_pubkey: Vec<u8>,
         uuid: uuid::Uuid,
     ) -> ResponsePayload<'static, HelloResponse> {
+        if self.shutdown.is_cancelled() {
+            return ResponsePayload::error(Error::Shutdown);
+        }
+
         if user_pubkey.len() != 65 {
-            return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                types::ErrorCode::InvalidRequest.code(), //BAD_REQUEST
-                "Public key must be 65 bytes",
-                None,
-            ));
+            return ResponsePayload::error(Error::InvalidPublicKeyLength {
+                length: user_pubkey.len(),
+            });
         };
 
         let mut nitro_rng = NitroRng::new(self.fd);
@@ -81,30 +414,23 @@ impl RpcServer for RpcServerImpl {
         let my_private_key = EphemeralSecret::random(&mut nitro_rng);
         let my_public_key = PublicKey::from(&my_private_key).to_sec1_bytes().to_vec();
 
+        //the nonce carries the tls public key hash so clients can pin the certificate
         let attestation = match utils::get_attestation(
             self.fd,
             Some(user_pubkey.clone()),
-            None,
+            self.tls_public_key_hash.clone(),
             Some(my_public_key.clone()),
         ) {
             Ok(attestation) => attestation,
             Err(err) => {
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InternalError.code(), //INTERNAL_SERVER_ERROR
-                    format!("{:?}", err),
-                    None,
-                ));
+                return ResponsePayload::error(Error::Attestation(format!("{:?}", err)));
             }
         };
 
         let their_public_key = match PublicKey::from_sec1_bytes(&user_pubkey) {
             Ok(pubkey) => pubkey,
             Err(err) => {
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InvalidParams.code(), //INVALID_PARAMS
-                    format!("{:?}", err),
-                    None,
-                ));
+                return ResponsePayload::error(Error::InvalidPublicKey(format!("{:?}", err)));
             }
         };
 
@@ -119,38 +445,51 @@ impl RpcServer for RpcServerImpl {
             .await
         {
             Ok(_) => (),
-            Err(_) => {
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InvalidRequest.code(), //INTERNAL_SERVER_ERROR
-                    "UUID already exists",
-                    None,
-                ));
+            Err(e) => {
+                return ResponsePayload::error(e);
             }
         }
 
-        ResponsePayload::success(HelloResponse::new(uuid, attestation).into())
+        ResponsePayload::success(HelloResponse::new(uuid, attestation))
     }
 
     //TODO: check if circuit exists
+    #[tracing::instrument(skip_all, fields(%uuid))]
     async fn submit_request(
         &self,
         uuid: uuid::Uuid,
         nonce: Vec<u8>,
         cipher_text: Vec<u8>,
         auth_tag: Vec<u8>,
-    ) -> ResponsePayload<'static, String> {
+    ) -> ResponsePayload<'static, SubmitResponse> {
+        if self.shutdown.is_cancelled() {
+            self.store.remove_agreement(&uuid).await;
+            return ResponsePayload::error(Error::Shutdown);
+        }
+
         let nonce = nonce.as_slice();
         let auth_tag = auth_tag.as_slice();
+        let payload_digest = utils::get_payload_digest(nonce, &cipher_text, auth_tag);
         let key = {
             let key = match self.store.get_shared_secret(&uuid).await {
                 Some(shared_secret) => shared_secret,
+                //the agreement is gone once a request has been accepted
                 None => {
-                    self.store.remove_agreement(&uuid).await;
-                    return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                        types::ErrorCode::InvalidRequest.code(),
-                        "UUID not found",
-                        None,
-                    ));
+                    return match self.previous_submission(uuid, &payload_digest).await {
+                        Ok(Some(response)) => ResponsePayload::success(response),
+                        Err(Error::PayloadConflict)
+                            if self
+                                .holds_accepted_secret(uuid, nonce, cipher_text, auth_tag)
+                                .await =>
+                        {
+                            ResponsePayload::error(Error::PayloadConflict)
+                        }
+                        //anyone else must not learn whether the uuid exists
+                        Ok(None) | Err(Error::PayloadConflict) => {
+                            ResponsePayload::error(Error::UuidNotFound)
+                        }
+                        Err(e) => ResponsePayload::error(e),
+                    };
                 }
             };
             key
@@ -160,130 +499,218 @@ impl RpcServer for RpcServerImpl {
             Ok(key) => key,
             Err(_) => {
                 self.store.remove_agreement(&uuid).await;
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InternalError.code(), //INTERNAL_SERVER_ERROR
-                    "Failed to store ephemeral key",
-                    None,
-                ));
+                return ResponsePayload::error(Error::InvalidSharedSecret);
             }
         };
 
+        let cancel_token = utils::get_cancel_token(&key);
+
         let decrypted_text: String = match utils::decrypt(key, cipher_text, auth_tag, nonce) {
             Ok(text) => text,
             Err(_) => {
                 self.store.remove_agreement(&uuid).await;
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InvalidRequest.code(),
-                    "Failed to decrypt text",
-                    None,
-                ));
+                return ResponsePayload::error(Error::DecryptionFailed);
             }
         };
 
-        let submit_request = match serde_json::from_str::<SubmitRequest>(&decrypted_text) {
-            Ok(submit_request) => {
-                let mut allowed_proof_type = "";
-                if cfg!(feature = "register") {
-                    allowed_proof_type = "register";
-                } else if cfg!(feature = "dsc") {
-                    allowed_proof_type = "dsc";
-                } else {
-                    allowed_proof_type = "disclose";
-                }
-
-                let invalid_proof_type_response =
-                    ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                        types::ErrorCode::InvalidRequest.code(), //BAD REQUEST
-                        format!("This endpoint only allows {} inputs", allowed_proof_type),
-                        None,
-                    ));
-
-                match submit_request.proof_request_type {
-                    ProofRequest::Register { .. } => {
-                        if !cfg!(feature = "register") {
-                            self.store.remove_agreement(&uuid).await;
-                            return invalid_proof_type_response;
-                        }
-                    }
-                    ProofRequest::Dsc { .. } => {
-                        if !cfg!(feature = "dsc") {
-                            self.store.remove_agreement(&uuid).await;
-                            return invalid_proof_type_response;
-                        }
-                    }
-                    ProofRequest::Disclose { .. } => {
-                        if !cfg!(feature = "disclose") {
-                            self.store.remove_agreement(&uuid).await;
-                            return invalid_proof_type_response;
-                        }
-                    }
-                };
+        let payload = match SubmitPayload::parse(&decrypted_text) {
+            Ok(payload) => payload,
+            Err(e) => {
+                self.store.remove_agreement(&uuid).await;
+                return ResponsePayload::error(Error::InvalidProofRequest(e.to_string()));
+            }
+        };
 
-                let circuit_name = submit_request.proof_request_type.circuit().name.clone();
-                if !self.circuit_zkey_map.contains_key(&circuit_name) {
+        let (batch_id, submit_requests) = match payload {
+            SubmitPayload::Single(submit_request) => (None, vec![(uuid, submit_request)]),
+            SubmitPayload::Batch(submit_requests) => {
+                if submit_requests.is_empty() || submit_requests.len() > self.max_batch_size {
                     self.store.remove_agreement(&uuid).await;
-                    return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                        types::ErrorCode::InvalidRequest.code(),
-                        format!("Could not find the given circuit name: {}", &circuit_name),
-                        None,
-                    ));
+                    return ResponsePayload::error(Error::InvalidProofRequest(format!(
+                        "a batch must have between 1 and {} requests",
+                        self.max_batch_size
+                    )));
                 }
-                submit_request
+                //the session uuid identifies the batch, its requests get their own
+                let mut nitro_rng = NitroRng::new(self.fd);
+                let submit_requests = submit_requests
+                    .into_iter()
+                    .map(|submit_request| {
+                        let mut bytes = [0u8; 16];
+                        nitro_rng.fill_bytes(&mut bytes);
+                        let item_uuid = uuid::Builder::from_random_bytes(bytes).into_uuid();
+                        (item_uuid, submit_request)
+                    })
+                    .collect();
+                (Some(uuid), submit_requests)
             }
-            Err(_) => {
+        };
+
+        let mut items = Vec::with_capacity(submit_requests.len());
+        for (item_uuid, mut submit_request) in submit_requests {
+            if let Err(e) = self.check_request(&submit_request) {
                 self.store.remove_agreement(&uuid).await;
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InvalidRequest.code(),
-                    "Failed to parse proof request",
-                    None,
-                ));
+                return ResponsePayload::error(e);
             }
-        };
+            let blocked = match self.resolve_dependency(&mut submit_request, &items).await {
+                Ok(blocked) => blocked,
+                Err(e) => {
+                    self.store.remove_agreement(&uuid).await;
+                    return ResponsePayload::error(e);
+                }
+            };
+            let circuit = submit_request.proof_request_type.circuit();
+            let cache_key = self.cache.as_ref().and_then(|cache| cache.key(circuit));
+            //a cached proof would finish the request before the one it depends on
+            let cached = if blocked {
+                None
+            } else {
+                self.find_cached(cache_key.as_deref()).await
+            };
+            let identifier_key_hash = submit_request
+                .proof_request_type
+                .identifier_key()
+                .map(utils::get_identifier_key_hash);
+            items.push(Item {
+                uuid: item_uuid,
+                submit_request,
+                cache_key,
+                cached,
+                identifier_key_hash,
+                blocked,
+            });
+        }
 
-        let (endpoint_type, endpoint) = match &submit_request.proof_request_type {
-            ProofRequest::Disclose {
-                endpoint_type,
-                endpoint,
-                ..
-            } => (Some(endpoint_type), Some(endpoint)),
-            _ => (None, None),
+        //nothing can fail once the requests are recorded, so that a retry never finds a
+        //submission that was answered with an error
+        let created = match self.insert_jobs(&items, &cancel_token).await {
+            Ok(cancellations) => {
+                let new_proofs: Vec<NewProof> = items
+                    .iter()
+                    .map(|item| item.new_proof(&payload_digest))
+                    .collect();
+                let created = match batch_id {
+                    Some(batch_id) => self.db.create_batch(batch_id, &new_proofs).await,
+                    None => self.db.create(&new_proofs[0]).await,
+                };
+                if created.is_err() {
+                    self.remove_jobs(&items).await;
+                }
+                created.map(|_| cancellations)
+            }
+            Err(e) => Err(e),
+        };
+        let cancellations = match created {
+            Ok(cancellations) => cancellations,
+            Err(e) => {
+                //a retry racing the first call, which still holds the agreement and keeps
+                //it once the request is accepted
+                if let Error::DuplicateUuid = e {
+                    return match self.previous_submission(uuid, &payload_digest).await {
+                        Ok(Some(response)) => ResponsePayload::success(response),
+                        Ok(None) => ResponsePayload::error(e),
+                        Err(e) => ResponsePayload::error(e),
+                    };
+                }
+                self.store.remove_agreement(&uuid).await;
+                return ResponsePayload::error(e);
+            }
         };
 
-        if let Err(e) = create_proof_status(
-            uuid,
-            &(&submit_request.proof_request_type).into(),
-            &submit_request.proof_request_type.circuit().name,
-            submit_request.onchain,
-            &self.db,
-            endpoint_type,
-            endpoint,
-        )
-        .await
-        {
-            self.store.remove_agreement(&uuid).await;
-            return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                types::ErrorCode::InternalError.code(), //INTERNAL_SERVER_ERROR
-                e,
-                None,
-            ));
+        let uuids: Vec<uuid::Uuid> = items.iter().map(|item| item.uuid).collect();
+        for (item, cancellation) in items.into_iter().zip(cancellations) {
+            self.start(item, cancellation).await;
         }
 
-        let file_generator = FileGenerator::new(uuid.clone(), submit_request.proof_request_type);
-        match self.file_generator_sender.send(file_generator).await {
-            Ok(()) => (),
-            Err(e) => {
-                self.store.remove_agreement(&uuid).await;
-                return ResponsePayload::error(ErrorObjectOwned::owned::<String>(
-                    types::ErrorCode::InternalError.code(), //INTERNAL_SERVER_ERROR
-                    e.to_string(),
-                    None,
-                ));
+        self.store.accept_agreement(&uuid).await;
+        match batch_id {
+            Some(batch_id) => {
+                tracing::info!(size = uuids.len(), "batch accepted");
+                ResponsePayload::success(SubmitResponse::Batch { batch_id, uuids })
             }
+            None => ResponsePayload::success(SubmitResponse::Single(uuid)),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(%uuid))]
+    async fn cancel(
+        &self,
+        uuid: uuid::Uuid,
+        cancel_token: Vec<u8>,
+    ) -> ResponsePayload<'static, String> {
+        //the pipeline kills any running child and drops queued work once it sees the cancellation
+        if let Err(e) = self.jobs.cancel_job(&uuid, &cancel_token).await {
+            return ResponsePayload::error(e);
         }
 
-        self.store.remove_agreement(&uuid).await;
+        if let Err(e) = self.db.cancel_proof(uuid).await {
+            return ResponsePayload::error(e);
+        }
+
+        let _ = remove_tmp_folder(get_tmp_folder_path(&uuid.to_string())).await;
+
+        tracing::info!("request cancelled");
         ResponsePayload::success(uuid.to_string())
     }
+
+    //unhealthy reports are returned as an error so that `GET /health` answers with a 500
+    async fn health(&self) -> ResponsePayload<'static, HealthResponse> {
+        let report = self.health.check().await;
+        if !report.healthy {
+            tracing::warn!(?report, "health check failed");
+            return ResponsePayload::error(Error::Unhealthy(Box::new(report)));
+        }
+        ResponsePayload::success(report)
+    }
+
+    async fn proofs_by_identifier(
+        &self,
+        identifier: String,
+        identifier_key: String,
+        before: Option<uuid::Uuid>,
+        limit: Option<u32>,
+    ) -> ResponsePayload<'static, ProofPage> {
+        if let Err(e) = utils::check_identifier(&identifier) {
+            return ResponsePayload::error(e);
+        }
+
+        //a wrong key finds nothing, like an identifier nobody used
+        let identifier_key_hash = utils::get_identifier_key_hash(&identifier_key);
+        let limit = limit
+            .unwrap_or(MAX_PROOFS_PER_IDENTIFIER)
+            .clamp(1, MAX_PROOFS_PER_IDENTIFIER);
+        //one more tells whether the page is the last one
+        match self
+            .db
+            .find_by_identifier(&identifier, &identifier_key_hash, before, limit + 1)
+            .await
+        {
+            Ok(mut records) => {
+                let truncated = records.len() > limit as usize;
+                records.truncate(limit as usize);
+                ResponsePayload::success(ProofPage {
+                    proofs: records.into_iter().map(ProofSummary::from).collect(),
+                    truncated,
+                })
+            }
+            Err(e) => ResponsePayload::error(e),
+        }
+    }
+
+    async fn batch_status(&self, batch_id: uuid::Uuid) -> ResponsePayload<'static, BatchStatus> {
+        match self.db.find_batch(batch_id).await {
+            Ok(records) if records.is_empty() => ResponsePayload::error(Error::UuidNotFound),
+            Ok(records) => {
+                let statuses: Vec<Status> = records.iter().map(|record| record.status).collect();
+                ResponsePayload::success(BatchStatus {
+                    batch_id,
+                    status: Status::of_batch(&statuses),
+                    proofs: records.into_iter().map(ProofSummary::from).collect(),
+                })
+            }
+            Err(e) => ResponsePayload::error(e),
+        }
+    }
 }
 
 pub struct NitroRng {
@@ -326,8 +753,7 @@ impl RngCore for NitroRng {
             let res = nsm_get_random(self.fd, dest.as_mut_ptr(), &mut buf_len);
             match res {
                 ErrorCode::Success => Ok(()),
-                _ => Err(rand_core::Error::new(io::Error::new(
-                    io::ErrorKind::Other,
+                _ => Err(rand_core::Error::new(io::Error::other(
                     "Could not generate random data",
                 ))),
             }
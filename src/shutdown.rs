@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+
+use crate::db::ProofStore;
+use crate::error::Error;
+use crate::store::JobStore;
+use crate::utils::remove_tmp_folders;
+
+//cancelled jobs only need to notice the cancellation and clean up after themselves
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Resolves on SIGTERM or ctrl-c.
+pub async fn wait_for_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+    tokio::select! {
+        _ = sigterm.recv() => tracing::info!("received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => tracing::info!("received SIGINT"),
+    }
+}
+
+/// Stops accepting new requests and drains the pipeline. Jobs still in flight once
+/// `timeout` has passed and blocked jobs are cancelled and marked as failed with a
+/// `Shutdown` error.
+pub async fn drain(
+    shutdown: &CancellationToken,
+    jobs: &JobStore,
+    db: &dyn ProofStore,
+    timeout: Duration,
+) {
+    shutdown.cancel();
+    tracing::info!(
+        in_flight = jobs.in_flight().await,
+        timeout_secs = timeout.as_secs(),
+        "draining in-flight requests"
+    );
+
+    let drained = wait_for_jobs(jobs, timeout).await;
+    //blocked requests are not waited for, nothing would unblock them once the server is gone
+    let remaining = jobs.cancel_all().await;
+    if !remaining.is_empty() {
+        tracing::warn!(
+            remaining = remaining.len(),
+            "failing requests still in flight or blocked"
+        );
+        for uuid in remaining {
+            let _ = db.fail_proof(uuid, &Error::Shutdown).await;
+        }
+    }
+    if !drained {
+        wait_for_jobs(jobs, CANCEL_GRACE_PERIOD).await;
+    }
+
+    remove_tmp_folders().await;
+}
+
+async fn wait_for_jobs(jobs: &JobStore, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while jobs.in_flight().await > 0 {
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    true
+}
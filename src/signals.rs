@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+//bytes packed into each field element of a disclose output, least significant first
+const BYTES_PER_FIELD: usize = 31;
+
+//positions in the unpacked revealed data. The disclose circuit of the openpassport
+//circuits (circuits/disclose/disclose.circom) reveals the 88 MRZ bytes of dg1 masked by
+//selector_dg1, followed by the two ascii digits of majority when selector_older_than is
+//set, and packs those 90 bytes with PackBytes. The MRZ offsets are those of the two
+//44 character lines of a TD3 passport in ICAO 9303 part 4: document code, issuing state
+//and name, then number, check digit, nationality, birth date, check digit, sex and expiry
+const REVEALED_ATTRIBUTES: [(&str, usize, usize); 8] = [
+    ("issuing_state", 2, 5),
+    ("name", 5, 44),
+    ("passport_number", 44, 53),
+    ("nationality", 54, 57),
+    ("date_of_birth", 57, 63),
+    ("gender", 64, 65),
+    ("expiry_date", 65, 71),
+    ("older_than", 88, 90),
+];
+
+/// Names of the public signals of each circuit, read from the `<circuit>.sym` file that
+/// circom writes for the circuit.
+#[derive(Default)]
+pub struct PublicSignals {
+    circuits: HashMap<String, Vec<String>>,
+}
+
+impl PublicSignals {
+    /// Reads `<circuit_folder>/<circuit>_cpp/<circuit>.sym` for every circuit. Circuits
+    /// without one only get their public inputs stored as an array.
+    pub fn load<'a>(
+        circuit_folder: &str,
+        circuit_names: impl Iterator<Item = &'a String>,
+    ) -> std::io::Result<Self> {
+        let mut circuits = HashMap::new();
+        for circuit_name in circuit_names {
+            let path = Path::new(circuit_folder)
+                .join(format!("{}_cpp", circuit_name))
+                .join(format!("{}.sym", circuit_name));
+            if !path.exists() {
+                tracing::warn!(
+                    circuit = circuit_name,
+                    "no symbol file, public signals are not named"
+                );
+                continue;
+            }
+            let file = std::io::BufReader::new(std::fs::File::open(path)?);
+            circuits.insert(circuit_name.clone(), main_signals(file)?);
+        }
+        Ok(Self { circuits })
+    }
+
+    /// The public inputs as an object keyed by signal name, with arrays for array
+    /// signals and the decoded `revealed_data` of disclose circuits. `None` if the
+    /// names of the circuit are unknown.
+    pub fn name(&self, circuit_name: &str, public_inputs: &[String]) -> Option<Value> {
+        let names = self.circuits.get(circuit_name)?;
+        if names.len() < public_inputs.len() {
+            tracing::warn!(
+                circuit = circuit_name,
+                "symbol file has fewer signals than the public inputs"
+            );
+            return None;
+        }
+
+        let mut signals = Map::new();
+        for (name, value) in names.iter().zip(public_inputs) {
+            let value = Value::String(value.clone());
+            //multi-dimensional arrays are flattened
+            match name.split_once('[') {
+                Some((base, _)) => {
+                    let entry = signals
+                        .entry(base)
+                        .or_insert_with(|| Value::Array(Vec::new()));
+                    if let Value::Array(values) = entry {
+                        values.push(value);
+                    }
+                }
+                None => {
+                    signals.insert(name.clone(), value);
+                }
+            }
+        }
+
+        if let Some(Value::Array(packed)) = signals.get("revealedData_packed") {
+            let revealed_data = revealed_data(packed);
+            signals.insert("revealed_data".to_string(), revealed_data);
+        }
+        Some(Value::Object(signals))
+    }
+}
+
+//the public signals are the first wires after the constant one: the outputs of the main
+//component, then its public inputs
+fn main_signals(sym: impl BufRead) -> std::io::Result<Vec<String>> {
+    let mut signals = Vec::new();
+    for line in sym.lines() {
+        let line = line?;
+        //<label index>,<witness index>,<component index>,<name>
+        let mut fields = line.splitn(4, ',');
+        let witness_index = fields.nth(1).and_then(|index| index.parse::<i64>().ok());
+        let name = fields.nth(1).and_then(|name| name.strip_prefix("main."));
+        if let (Some(witness_index), Some(name)) = (witness_index, name) {
+            if witness_index > 0 && !name.contains('.') {
+                signals.push((witness_index, name.to_string()));
+            }
+        }
+    }
+    signals.sort_by_key(|(witness_index, _)| *witness_index);
+    Ok(signals.into_iter().map(|(_, name)| name).collect())
+}
+
+/// Unpacks the revealed passport attributes. Attributes that were not disclosed are
+/// all zeros, they are left out like anything else that is not printable ascii.
+fn revealed_data(packed: &[Value]) -> Value {
+    let mut bytes = Vec::with_capacity(packed.len() * BYTES_PER_FIELD);
+    for field in packed {
+        match field.as_str().and_then(field_bytes) {
+            Some(field) => bytes.extend(field),
+            None => return Value::Null,
+        }
+    }
+
+    let mut attributes = Map::new();
+    for (name, start, end) in REVEALED_ATTRIBUTES {
+        let Some(value) = bytes.get(start..end) else {
+            continue;
+        };
+        if !value.iter().all(u8::is_ascii_graphic) {
+            continue;
+        }
+        //the MRZ pads with '<', which also separates the surname from the given names
+        let value: String = value.iter().map(|b| *b as char).collect();
+        let value = value.replace('<', " ");
+        attributes.insert(name.to_string(), Value::String(value.trim().to_string()));
+    }
+    Value::Object(attributes)
+}
+
+//converts a decimal field element to its little endian bytes
+fn field_bytes(decimal: &str) -> Option<Vec<u8>> {
+    let mut digits: Vec<u32> = decimal
+        .chars()
+        .map(|c| c.to_digit(10))
+        .collect::<Option<_>>()?;
+    let mut bytes = Vec::with_capacity(BYTES_PER_FIELD);
+    while digits.iter().any(|d| *d != 0) {
+        let mut remainder = 0;
+        for digit in digits.iter_mut() {
+            let value = remainder * 10 + *digit;
+            *digit = value / 256;
+            remainder = value % 256;
+        }
+        bytes.push(remainder as u8);
+    }
+    if bytes.len() > BYTES_PER_FIELD {
+        return None;
+    }
+    bytes.resize(BYTES_PER_FIELD, 0);
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //the specimen passport of ICAO 9303 part 4
+    const MRZ: &str = concat!(
+        "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<",
+        "L898902C36UTO7408122F1204159ZE184226B<<<<<10",
+    );
+
+    const SYM: &str = "\
+1,1,0,main.revealedData_packed[0]
+2,2,0,main.revealedData_packed[1]
+3,3,0,main.revealedData_packed[2]
+4,4,0,main.nullifier
+5,5,0,main.scope
+6,-1,0,main.dg1[0]
+7,6,1,main.hasher.out
+8,7,0,main.secret
+";
+
+    //the inverse of field_bytes, packs 31 bytes per field element
+    fn pack(bytes: &[u8]) -> Vec<Value> {
+        bytes
+            .chunks(BYTES_PER_FIELD)
+            .map(|chunk| {
+                let mut digits = vec![0u32];
+                for byte in chunk.iter().rev() {
+                    let mut carry = *byte as u32;
+                    for digit in digits.iter_mut().rev() {
+                        let value = *digit * 256 + carry;
+                        *digit = value % 10;
+                        carry = value / 10;
+                    }
+                    while carry > 0 {
+                        digits.insert(0, carry % 10);
+                        carry /= 10;
+                    }
+                }
+                let decimal: String = digits.iter().map(|d| d.to_string()).collect();
+                Value::String(decimal)
+            })
+            .collect()
+    }
+
+    fn revealed(older_than: &str) -> Vec<u8> {
+        [MRZ.as_bytes(), older_than.as_bytes()].concat()
+    }
+
+    #[test]
+    fn field_bytes_are_little_endian() {
+        let expected = |prefix: &[u8]| {
+            let mut bytes = prefix.to_vec();
+            bytes.resize(BYTES_PER_FIELD, 0);
+            Some(bytes)
+        };
+        assert_eq!(field_bytes("0"), expected(&[]));
+        assert_eq!(field_bytes("1"), expected(&[1]));
+        assert_eq!(field_bytes("256"), expected(&[0, 1]));
+        //"P<"
+        assert_eq!(field_bytes("15440"), expected(b"P<"));
+        //2^248 - 1 fills the 31 bytes, 2^248 does not fit
+        assert_eq!(
+            field_bytes(
+                "452312848583266388373324160190187140051835877600158453279131187530910662655"
+            ),
+            Some(vec![255; BYTES_PER_FIELD])
+        );
+        assert_eq!(
+            field_bytes(
+                "452312848583266388373324160190187140051835877600158453279131187530910662656"
+            ),
+            None
+        );
+        assert_eq!(field_bytes("12a"), None);
+        assert_eq!(field_bytes("-1"), None);
+    }
+
+    #[test]
+    fn unpacks_revealed_data() {
+        let packed = pack(&revealed("18"));
+        assert_eq!(packed.len(), 3);
+        assert_eq!(
+            revealed_data(&packed),
+            serde_json::json!({
+                "issuing_state": "UTO",
+                "name": "ERIKSSON  ANNA MARIA",
+                "passport_number": "L898902C3",
+                "nationality": "UTO",
+                "date_of_birth": "740812",
+                "gender": "F",
+                "expiry_date": "120415",
+                "older_than": "18",
+            })
+        );
+    }
+
+    #[test]
+    fn leaves_out_attributes_that_are_not_printable() {
+        let mut bytes = revealed("\0\0");
+        //the name and nationality were not disclosed
+        bytes[5..44].fill(0);
+        bytes[54..57].fill(0);
+        //a control character in the expiry date
+        bytes[66] = b'\n';
+        assert_eq!(
+            revealed_data(&pack(&bytes)),
+            serde_json::json!({
+                "issuing_state": "UTO",
+                "passport_number": "L898902C3",
+                "date_of_birth": "740812",
+                "gender": "F",
+            })
+        );
+        assert_eq!(
+            revealed_data(&[Value::String("x".to_string())]),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn reads_public_signals_from_sym() {
+        assert_eq!(
+            main_signals(SYM.as_bytes()).unwrap(),
+            [
+                "revealedData_packed[0]",
+                "revealedData_packed[1]",
+                "revealedData_packed[2]",
+                "nullifier",
+                "scope",
+                "secret",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_public_inputs() {
+        let signals = PublicSignals {
+            circuits: HashMap::from([(
+                "disclose".to_string(),
+                main_signals(SYM.as_bytes()).unwrap(),
+            )]),
+        };
+        let mut public_inputs: Vec<String> = pack(&revealed("18"))
+            .into_iter()
+            .map(|field| field.as_str().unwrap().to_string())
+            .collect();
+        public_inputs.extend(["7".to_string(), "8".to_string()]);
+
+        let named = signals.name("disclose", &public_inputs).unwrap();
+        assert_eq!(named["revealedData_packed"].as_array().unwrap().len(), 3);
+        assert_eq!(named["nullifier"], "7");
+        assert_eq!(named["scope"], "8");
+        assert_eq!(named["revealed_data"]["older_than"], "18");
+        assert_eq!(signals.name("register", &public_inputs), None);
+        assert_eq!(signals.name("disclose", &vec![String::new(); 7]), None);
+    }
+}
@@ -1,17 +1,25 @@
+use std::collections::HashMap;
 use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
 
-// use std::collections::HashMap;
 use lru::LruCache;
-use tokio::sync::Mutex;
+use subtle::ConstantTimeEq;
+use tokio::sync::{Mutex, Notify};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::Error;
 
 pub struct LruStore {
     ecdh_store: Mutex<LruCache<String, Vec<u8>>>,
+    //kept apart so that accepted requests do not crowd out the pending handshakes
+    accepted: Mutex<LruCache<String, Vec<u8>>>,
 }
 
 impl LruStore {
     pub fn new(size: usize) -> Self {
         Self {
             ecdh_store: Mutex::new(LruCache::new(NonZeroUsize::new(size).unwrap())),
+            accepted: Mutex::new(LruCache::new(NonZeroUsize::new(size).unwrap())),
         }
     }
 }
@@ -21,25 +29,227 @@ impl LruStore {
         &self,
         uuid: uuid::Uuid,
         shared_secret: Vec<u8>,
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let mut cache = self.ecdh_store.lock().await;
 
         if cache.contains(&uuid.to_string()) {
-            return Err("Duplicate uuid".to_string());
+            return Err(Error::DuplicateUuid);
         } else {
             cache.put(uuid.to_string(), shared_secret);
         }
 
-        return Ok(());
+        metrics::gauge!("ecdh_store_size").set(cache.len() as f64);
+
+        Ok(())
     }
 
     pub async fn get_shared_secret(&self, uuid: &uuid::Uuid) -> Option<Vec<u8>> {
         let mut cache = self.ecdh_store.lock().await;
-        cache.get(&uuid.to_string()).map(|x| x.clone())
+        cache.get(&uuid.to_string()).cloned()
     }
 
     pub async fn remove_agreement(&self, uuid: &uuid::Uuid) {
         let mut cache = self.ecdh_store.lock().await;
         cache.pop(&uuid.to_string());
+        metrics::gauge!("ecdh_store_size").set(cache.len() as f64);
+    }
+
+    /// Ends the handshake of an accepted request but keeps its shared secret, so that
+    /// its client can be told apart from anyone else submitting under the UUID.
+    pub async fn accept_agreement(&self, uuid: &uuid::Uuid) {
+        let mut cache = self.ecdh_store.lock().await;
+        if let Some(shared_secret) = cache.pop(&uuid.to_string()) {
+            self.accepted
+                .lock()
+                .await
+                .put(uuid.to_string(), shared_secret);
+        }
+        metrics::gauge!("ecdh_store_size").set(cache.len() as f64);
+    }
+
+    /// The shared secret of a request accepted with `accept_agreement`, if it has not
+    /// been evicted since.
+    pub async fn get_accepted_secret(&self, uuid: &uuid::Uuid) -> Option<Vec<u8>> {
+        let mut accepted = self.accepted.lock().await;
+        accepted.get(&uuid.to_string()).cloned()
+    }
+}
+
+struct Job {
+    cancel_token: Vec<u8>,
+    cancellation: CancellationToken,
+    /// When the job was accepted, or unblocked.
+    accepted_at: Instant,
+    /// Waiting for the request it depends on, not yet in the pipeline.
+    blocked: bool,
+}
+
+/// Requests that have been accepted by `submit_request` and are still in the pipeline.
+#[derive(Default)]
+pub struct JobStore {
+    jobs: Mutex<HashMap<uuid::Uuid, Job>>,
+    removed: Notify,
+}
+
+impl JobStore {
+    /// Registers an accepted request. A `blocked` job does not count as in flight until
+    /// it is unblocked.
+    pub async fn insert_job(
+        &self,
+        uuid: uuid::Uuid,
+        cancel_token: Vec<u8>,
+        blocked: bool,
+    ) -> Result<CancellationToken, Error> {
+        let mut jobs = self.jobs.lock().await;
+
+        if jobs.contains_key(&uuid) {
+            return Err(Error::DuplicateUuid);
+        }
+
+        let cancellation = CancellationToken::new();
+        jobs.insert(
+            uuid,
+            Job {
+                cancel_token,
+                cancellation: cancellation.clone(),
+                accepted_at: Instant::now(),
+                blocked,
+            },
+        );
+        metrics::gauge!("jobs_in_flight").set(in_flight(&jobs) as f64);
+
+        Ok(cancellation)
+    }
+
+    pub async fn cancel_job(&self, uuid: &uuid::Uuid, cancel_token: &[u8]) -> Result<(), Error> {
+        let jobs = self.jobs.lock().await;
+
+        let job = match jobs.get(uuid) {
+            Some(job) => job,
+            None => return Err(Error::UuidNotFound),
+        };
+
+        if !bool::from(job.cancel_token.ct_eq(cancel_token)) {
+            return Err(Error::InvalidCancelToken);
+        }
+
+        if job.cancellation.is_cancelled() {
+            return Err(Error::AlreadyCancelled);
+        }
+
+        job.cancellation.cancel();
+
+        Ok(())
+    }
+
+    /// Cancels every job that has not been cancelled yet and returns their UUIDs.
+    pub async fn cancel_all(&self) -> Vec<uuid::Uuid> {
+        let jobs = self.jobs.lock().await;
+        jobs.iter()
+            .filter(|(_, job)| !job.cancellation.is_cancelled())
+            .map(|(uuid, job)| {
+                job.cancellation.cancel();
+                *uuid
+            })
+            .collect()
+    }
+
+    /// Cancels the job of a request that was failed outside the pipeline, without a
+    /// cancel token.
+    pub async fn cancel(&self, uuid: &uuid::Uuid) {
+        if let Some(job) = self.jobs.lock().await.get(uuid) {
+            job.cancellation.cancel();
+        }
+    }
+
+    pub async fn is_cancelled(&self, uuid: &uuid::Uuid) -> bool {
+        let jobs = self.jobs.lock().await;
+        jobs.get(uuid)
+            .is_some_and(|job| job.cancellation.is_cancelled())
+    }
+
+    /// Moves a blocked job into the pipeline, it is aged from now on.
+    pub async fn unblock(&self, uuid: &uuid::Uuid) {
+        let mut jobs = self.jobs.lock().await;
+        if let Some(job) = jobs.get_mut(uuid) {
+            job.blocked = false;
+            job.accepted_at = Instant::now();
+        }
+        metrics::gauge!("jobs_in_flight").set(in_flight(&jobs) as f64);
+    }
+
+    /// The number of jobs in the pipeline, blocked jobs left out.
+    pub async fn in_flight(&self) -> usize {
+        in_flight(&*self.jobs.lock().await)
+    }
+
+    pub async fn active_jobs(&self) -> Vec<uuid::Uuid> {
+        self.jobs.lock().await.keys().copied().collect()
+    }
+
+    /// How long the oldest request still in the pipeline has been waiting, blocked jobs
+    /// left out.
+    pub async fn oldest_job_age(&self) -> Option<Duration> {
+        let jobs = self.jobs.lock().await;
+        jobs.values()
+            .filter(|job| !job.blocked)
+            .map(|job| job.accepted_at.elapsed())
+            .max()
+    }
+
+    pub async fn remove_job(&self, uuid: &uuid::Uuid) {
+        let mut jobs = self.jobs.lock().await;
+        jobs.remove(uuid);
+        metrics::gauge!("jobs_in_flight").set(in_flight(&jobs) as f64);
+        self.removed.notify_one();
+    }
+
+    /// Resolves once a job has left the pipeline, finished or not. A job removed while
+    /// nobody waits wakes the next call.
+    pub async fn job_removed(&self) {
+        self.removed.notified().await;
+    }
+}
+
+fn in_flight(jobs: &HashMap<uuid::Uuid, Job>) -> usize {
+    jobs.values().filter(|job| !job.blocked).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn accepted_agreements_keep_their_secret() {
+        let store = LruStore::new(1);
+        let (accepted, pending) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        store.insert_new_agreement(accepted, vec![1]).await.unwrap();
+        store.accept_agreement(&accepted).await;
+        assert_eq!(store.get_shared_secret(&accepted).await, None);
+        assert_eq!(store.get_accepted_secret(&accepted).await, Some(vec![1]));
+
+        //an accepted request does not take the place of a pending handshake
+        store.insert_new_agreement(pending, vec![2]).await.unwrap();
+        assert_eq!(store.get_shared_secret(&pending).await, Some(vec![2]));
+        assert_eq!(store.get_accepted_secret(&accepted).await, Some(vec![1]));
+        assert_eq!(store.get_accepted_secret(&pending).await, None);
+    }
+
+    #[tokio::test]
+    async fn blocked_jobs_are_not_in_flight() {
+        let jobs = JobStore::default();
+        let (running, blocked) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        jobs.insert_job(running, vec![1], false).await.unwrap();
+        jobs.insert_job(blocked, vec![1], true).await.unwrap();
+        assert_eq!(jobs.in_flight().await, 1);
+        assert_eq!(jobs.active_jobs().await.len(), 2);
+
+        jobs.remove_job(&running).await;
+        assert_eq!(jobs.in_flight().await, 0);
+        assert_eq!(jobs.oldest_job_age().await, None);
+
+        jobs.unblock(&blocked).await;
+        assert_eq!(jobs.in_flight().await, 1);
+        assert!(jobs.oldest_job_age().await.is_some());
     }
 }
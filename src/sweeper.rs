@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::ProofStore;
+use crate::store::JobStore;
+use crate::types::ProofType;
+use crate::utils::{list_tmp_folders, remove_tmp_folder};
+
+/// Removes leftovers of requests that are no longer in the pipeline, e.g. after a crash.
+/// The first sweep runs immediately.
+pub async fn run(
+    db: Arc<dyn ProofStore>,
+    jobs: Arc<JobStore>,
+    interval: Duration,
+    stuck_after: Duration,
+    blocked_after: Duration,
+    proof_types: Vec<ProofType>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        sweep_tmp_folders(&jobs).await;
+        sweep_stuck_requests(&*db, &jobs, stuck_after, &proof_types).await;
+        sweep_blocked_requests(&*db, &jobs, blocked_after).await;
+    }
+}
+
+async fn sweep_tmp_folders(jobs: &JobStore) {
+    let folders = list_tmp_folders().await;
+
+    //a job is registered before its folder is created, so this has to be read after listing
+    let active: HashSet<uuid::Uuid> = jobs.active_jobs().await.into_iter().collect();
+
+    let mut removed = 0;
+    for (uuid, path) in folders {
+        if uuid.is_some_and(|uuid| active.contains(&uuid)) {
+            continue;
+        }
+        if remove_tmp_folder(&path).await.is_ok() {
+            removed += 1;
+        }
+    }
+
+    if removed > 0 {
+        tracing::info!(removed, "removed orphaned tmp folders");
+        metrics::counter!("swept_tmp_folders_total").increment(removed);
+    }
+}
+
+//other servers sharing the database prove other proof types
+async fn sweep_stuck_requests(
+    db: &dyn ProofStore,
+    jobs: &JobStore,
+    stuck_after: Duration,
+    proof_types: &[ProofType],
+) {
+    let active = jobs.active_jobs().await;
+    if let Ok(failed) = db
+        .fail_stuck_proofs(stuck_after, proof_types, &active)
+        .await
+    {
+        if failed > 0 {
+            tracing::warn!(failed, "failed stuck requests");
+            metrics::counter!("swept_requests_total").increment(failed);
+        }
+    }
+}
+
+//the holds of the failed requests are dropped once their jobs are cancelled
+async fn sweep_blocked_requests(db: &dyn ProofStore, jobs: &JobStore, blocked_after: Duration) {
+    if let Ok(failed) = db.fail_blocked_proofs(blocked_after).await {
+        for uuid in &failed {
+            jobs.cancel(uuid).await;
+        }
+        if !failed.is_empty() {
+            tracing::warn!(
+                failed = failed.len(),
+                "failed requests blocked for too long"
+            );
+            metrics::counter!("swept_requests_total").increment(failed.len() as u64);
+        }
+    }
+}
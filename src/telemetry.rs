@@ -0,0 +1,158 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::Response;
+use hyper_util::rt::TokioIo;
+use jsonrpsee::server::middleware::rpc::RpcServiceT;
+use jsonrpsee::server::MethodResponse;
+use jsonrpsee::types::Request;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tokio::sync::mpsc::WeakSender;
+
+use crate::transport::{Address, Listener, Stream, ACCEPT_BACKOFF};
+
+//stage durations range from a few seconds for disclose witnesses to minutes for register proofs
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+//histograms are drained into their summaries on every upkeep
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Serves the prometheus exposition format on `address` and returns the bound address.
+pub async fn install(address: &Address) -> std::io::Result<Address> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("duration_seconds".to_string()),
+            DURATION_BUCKETS,
+        )
+        .and_then(PrometheusBuilder::install_recorder)
+        .map_err(std::io::Error::other)?;
+
+    let listener = Listener::bind(address).await?;
+    let local_address = listener.local_address()?;
+
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_metrics(stream, handle.clone()));
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "could not accept metrics connection");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+            }
+        }
+    });
+
+    Ok(local_address)
+}
+
+//every path answers with the metrics, like the exporter's own listener
+async fn serve_metrics(stream: Stream, handle: PrometheusHandle) {
+    let service = service_fn(move |_| {
+        let body = handle.render();
+        async move {
+            Response::builder()
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Full::new(Bytes::from(body)))
+        }
+    });
+    if let Err(e) = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .await
+    {
+        tracing::debug!(error = %e, "metrics connection closed");
+    }
+}
+
+/// Counts and times every call to the `openpassport` methods.
+#[derive(Clone)]
+pub struct RpcMetrics<S> {
+    service: S,
+}
+
+impl<S> RpcMetrics<S> {
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+impl<'a, S> RpcServiceT<'a> for RpcMetrics<S>
+where
+    S: RpcServiceT<'a> + Send + Sync + Clone + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = MethodResponse> + Send + 'a>>;
+
+    fn call(&self, request: Request<'a>) -> Self::Future {
+        let service = self.service.clone();
+        //clients choose the method name, so unknown methods share one label
+        let method = match request.method_name() {
+            name @ ("openpassport_hello"
+            | "openpassport_submit_request"
+            | "openpassport_cancel"
+            | "openpassport_health"
+            | "openpassport_proofs_by_identifier"
+            | "openpassport_batch_status") => name.to_string(),
+            _ => "unknown".to_string(),
+        };
+
+        Box::pin(async move {
+            let started = Instant::now();
+            let response = service.call(request).await;
+            let result = if response.is_success() { "ok" } else { "error" };
+
+            metrics::counter!("rpc_requests_total", "method" => method.clone(), "result" => result)
+                .increment(1);
+            metrics::histogram!("rpc_request_duration_seconds", "method" => method)
+                .record(started.elapsed().as_secs_f64());
+
+            response
+        })
+    }
+}
+
+/// Periodically records how many jobs are waiting in each pipeline queue.
+/// Only weak senders are held so the sampler never keeps a queue open.
+pub async fn sample_queue_lengths<F, W, P>(
+    file_queue: WeakSender<F>,
+    witness_queue: WeakSender<W>,
+    proof_queue: WeakSender<P>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        record_queue_length("file", &file_queue);
+        record_queue_length("witness", &witness_queue);
+        record_queue_length("proof", &proof_queue);
+    }
+}
+
+fn record_queue_length<T>(queue: &'static str, sender: &WeakSender<T>) {
+    if let Some(length) = queue_length(sender) {
+        metrics::gauge!("queue_length", "queue" => queue).set(length as f64);
+    }
+}
+
+/// Number of jobs waiting in a queue, or `None` once the queue has been closed.
+pub fn queue_length<T>(sender: &WeakSender<T>) -> Option<usize> {
+    sender
+        .upgrade()
+        .map(|sender| sender.max_capacity() - sender.capacity())
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use p256::pkcs8::EncodePrivateKey;
+use p256::SecretKey;
+use rcgen::{CertificateParams, DnType, KeyPair};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use sha2::{Digest, Sha256};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::server::NitroRng;
+use crate::transport::Stream;
+
+const SERVER_NAME: &str = "tee-server";
+
+/// TLS for the RPC server with a self-signed certificate whose key never leaves the
+/// enclave. Clients pin it through the hash in the attestation document instead of a CA.
+#[derive(Clone)]
+pub struct Tls {
+    acceptor: TlsAcceptor,
+    public_key_hash: Vec<u8>,
+}
+
+impl Tls {
+    pub fn generate(fd: i32) -> Result<Self, String> {
+        let secret_key = SecretKey::random(&mut NitroRng::new(fd));
+        let pkcs8 = secret_key
+            .to_pkcs8_der()
+            .map_err(|e| format!("could not encode the key: {}", e))?;
+        let key_pair = KeyPair::try_from(pkcs8.as_bytes())
+            .map_err(|e| format!("could not load the key: {}", e))?;
+
+        let certificate = CertificateParams::new(vec![SERVER_NAME.to_string()])
+            .and_then(|mut params| {
+                params
+                    .distinguished_name
+                    .push(DnType::CommonName, SERVER_NAME);
+                params.self_signed(&key_pair)
+            })
+            .map_err(|e| format!("could not create the certificate: {}", e))?;
+
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .and_then(|builder| {
+                    builder.with_no_client_auth().with_single_cert(
+                        vec![certificate.der().clone()],
+                        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der())),
+                    )
+                })
+                .map_err(|e| format!("could not configure tls: {}", e))?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            public_key_hash: Sha256::digest(key_pair.public_key_der()).to_vec(),
+        })
+    }
+
+    /// SHA-256 of the DER encoded SubjectPublicKeyInfo of the certificate.
+    pub fn public_key_hash(&self) -> &[u8] {
+        &self.public_key_hash
+    }
+
+    pub async fn accept(&self, stream: Stream) -> std::io::Result<TlsStream<Stream>> {
+        self.acceptor.accept(stream).await
+    }
+}
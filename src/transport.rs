@@ -0,0 +1,290 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use serde::{Serialize, Serializer};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener};
+use tokio_vsock::{VsockAddr, VsockListener, VsockStream, VMADDR_CID_ANY};
+
+/// How long to wait before accepting again after an error. Errors such as running out of
+/// file descriptors persist, retrying right away would spin.
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A TCP `host:port` or a vsock `vsock:<cid>:<port>` address. `vsock:<port>` listens on
+/// every CID.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(String),
+    Vsock { cid: u32, port: u32 },
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let Some(vsock) = value.strip_prefix("vsock:") else {
+            if !value.contains(':') {
+                return Err(format!("expected <HOST>:<PORT>, got {}", value));
+            }
+            return Ok(Address::Tcp(value.to_string()));
+        };
+
+        let parse = |number: &str| {
+            number
+                .parse::<u32>()
+                .map_err(|e| format!("invalid vsock address {}: {}", value, e))
+        };
+        match vsock.split_once(':') {
+            Some((cid, port)) => Ok(Address::Vsock {
+                cid: parse(cid)?,
+                port: parse(port)?,
+            }),
+            None => Ok(Address::Vsock {
+                cid: VMADDR_CID_ANY,
+                port: parse(vsock)?,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            Address::Vsock { cid, port } if *cid == VMADDR_CID_ANY => write!(f, "vsock:{}", port),
+            Address::Vsock { cid, port } => write!(f, "vsock:{}:{}", cid, port),
+        }
+    }
+}
+
+impl Address {
+    pub async fn connect(&self) -> io::Result<Stream> {
+        match self {
+            Address::Tcp(address) => Ok(Stream::Tcp(TcpStream::connect(address).await?)),
+            Address::Vsock { cid, port } => Ok(Stream::Vsock(
+                VsockStream::connect(VsockAddr::new(*cid, *port)).await?,
+            )),
+        }
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Vsock(VsockListener),
+}
+
+impl Listener {
+    pub async fn bind(address: &Address) -> io::Result<Self> {
+        match address {
+            Address::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+            Address::Vsock { cid, port } => Ok(Listener::Vsock(VsockListener::bind(
+                VsockAddr::new(*cid, *port),
+            )?)),
+        }
+    }
+
+    /// The bound address, with the port the OS picked if it was 0.
+    pub fn local_address(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?.to_string())),
+            Listener::Vsock(listener) => {
+                let address = listener.local_addr()?;
+                Ok(Address::Vsock {
+                    cid: address.cid(),
+                    port: address.port(),
+                })
+            }
+        }
+    }
+
+    /// Accepts a connection and returns it along with the peer address.
+    pub async fn accept(&self) -> io::Result<(Stream, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), peer.to_string()))
+            }
+            Listener::Vsock(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Stream::Vsock(stream), peer.to_string()))
+            }
+        }
+    }
+}
+
+/// Forwards every connection made to the unix socket at `path` to a stream opened by
+/// `connect`, for clients such as the sqlx pool that can only connect over TCP or unix
+/// sockets.
+pub fn bridge<C, F, S>(path: &Path, connect: C) -> io::Result<()>
+where
+    C: Fn() -> F + Send + 'static,
+    F: Future<Output = io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    tokio::spawn(async move {
+        loop {
+            let mut inbound = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "could not accept bridge connection");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let outbound = connect();
+            tokio::spawn(async move {
+                let mut outbound = match outbound.await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "could not connect bridge");
+                        return;
+                    }
+                };
+                if let Err(e) = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await {
+                    tracing::debug!(error = %e, "bridge connection closed");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Vsock(VsockStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Vsock(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Vsock(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Vsock(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Vsock(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(
+            "vsock:3:8889".parse(),
+            Ok(Address::Vsock { cid: 3, port: 8889 })
+        );
+        assert_eq!(
+            "vsock:8888".parse(),
+            Ok(Address::Vsock {
+                cid: VMADDR_CID_ANY,
+                port: 8888
+            })
+        );
+        assert_eq!(
+            "localhost:5432".parse(),
+            Ok(Address::Tcp("localhost:5432".to_string()))
+        );
+        assert_eq!(
+            "0.0.0.0:3001".parse(),
+            Ok(Address::Tcp("0.0.0.0:3001".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        for address in [
+            "localhost",
+            "vsock:",
+            "vsock:3:",
+            "vsock:x:8889",
+            "vsock:3:8889:1",
+        ] {
+            assert!(address.parse::<Address>().is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn displays_what_it_parses() {
+        for address in ["vsock:3:8889", "vsock:8888", "127.0.0.1:3001"] {
+            assert_eq!(address.parse::<Address>().unwrap().to_string(), address);
+        }
+    }
+
+    #[tokio::test]
+    async fn bridges_to_tcp() {
+        let listener = Listener::bind(&"127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let address = listener.local_address().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut message = [0; 4];
+            stream.read_exact(&mut message).await.unwrap();
+            stream.write_all(&message).await.unwrap();
+        });
+
+        let path = std::env::temp_dir().join(format!("bridge-{}.sock", uuid::Uuid::new_v4()));
+        bridge(&path, move || {
+            let address = address.clone();
+            async move { address.connect().await }
+        })
+        .unwrap();
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,8 @@
+use clap::ValueEnum;
 use jsonrpsee::ResponsePayload;
 use serde::{Deserialize, Serialize};
 
+use crate::db::types::{ProofRecord, Status};
 use crate::generator::Circuit;
 
 #[derive(Serialize, Clone)]
@@ -15,21 +17,167 @@ impl HelloResponse {
     }
 }
 
-impl<'a> Into<ResponsePayload<'a, HelloResponse>> for HelloResponse {
-    fn into(self) -> ResponsePayload<'a, HelloResponse> {
-        ResponsePayload::success(self)
+impl<'a> From<HelloResponse> for ResponsePayload<'a, HelloResponse> {
+    fn from(val: HelloResponse) -> Self {
+        ResponsePayload::success(val)
     }
 }
 
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthResponse {
+    pub healthy: bool,
+    pub nsm: ComponentHealth,
+    pub db: ComponentHealth,
+    pub prover: ComponentHealth,
+    pub disk: DiskHealth,
+    pub queues: QueueHealth,
+    pub jobs: JobHealth,
+    pub shutting_down: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ComponentHealth {
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<E: std::fmt::Display> From<Result<(), E>> for ComponentHealth {
+    fn from(result: Result<(), E>) -> Self {
+        ComponentHealth {
+            healthy: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DiskHealth {
+    pub healthy: bool,
+    pub free_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QueueHealth {
+    pub healthy: bool,
+    pub file: Option<usize>,
+    pub witness: Option<usize>,
+    pub proof: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct JobHealth {
+    pub healthy: bool,
+    pub in_flight: usize,
+    pub oldest_age_secs: Option<u64>,
+}
+
+/// A request as returned by `proofs_by_identifier`. The proof and public inputs are
+/// left out, they are delivered to the endpoint of the request.
+#[derive(Serialize, Clone, Debug)]
+pub struct ProofSummary {
+    pub uuid: uuid::Uuid,
+    pub proof_type: ProofType,
+    pub status: Status,
+    pub circuit_name: String,
+    pub onchain: bool,
+    pub created_at: Option<String>,
+    pub witness_generated_at: Option<String>,
+    pub proof_generated_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<uuid::Uuid>,
+}
+
+//timestamps are sent as RFC 3339 strings
+impl From<ProofRecord> for ProofSummary {
+    fn from(record: ProofRecord) -> Self {
+        ProofSummary {
+            uuid: record.request_id,
+            proof_type: record.proof_type,
+            status: record.status,
+            circuit_name: record.circuit_name,
+            onchain: record.onchain,
+            created_at: record.created_at.map(|t| t.to_rfc3339()),
+            witness_generated_at: record.witness_generated_at.map(|t| t.to_rfc3339()),
+            proof_generated_at: record.proof_generated_at.map(|t| t.to_rfc3339()),
+            error_code: record.error_code,
+            depends_on: record.depends_on,
+        }
+    }
+}
+
+/// A page of the requests submitted with an identifier, the most recent first.
+#[derive(Serialize, Clone, Debug)]
+pub struct ProofPage {
+    pub proofs: Vec<ProofSummary>,
+    /// Older requests are left, they are listed with the UUID of the last one as `before`.
+    pub truncated: bool,
+}
+
+/// The status of a batch as a whole, with its requests in order.
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchStatus {
+    pub batch_id: uuid::Uuid,
+    pub status: Status,
+    pub proofs: Vec<ProofSummary>,
+}
+
 #[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct SubmitRequest {
     pub onchain: bool,
+    /// A request whose proof has to be generated first, e.g. the DSC proof a register
+    /// proof builds on.
+    #[serde(default)]
+    pub depends_on: Option<uuid::Uuid>,
+    /// The position of an earlier request of the same batch to depend on, for requests
+    /// whose dependency is submitted alongside them.
+    #[serde(default)]
+    pub depends_on_index: Option<usize>,
     #[serde(flatten)]
     pub proof_request_type: ProofRequest,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Deserialize)]
+struct BatchRequest {
+    requests: Vec<SubmitRequest>,
+}
+
+/// The decrypted payload of `submit_request`: a single request, or a batch of requests
+/// accepted together in one session.
+pub enum SubmitPayload {
+    Single(SubmitRequest),
+    Batch(Vec<SubmitRequest>),
+}
+
+impl SubmitPayload {
+    //a batch is an object with a `requests` array, anything else is a single request
+    pub fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        if value.get("requests").is_some() {
+            let batch: BatchRequest = serde_json::from_value(value)?;
+            Ok(SubmitPayload::Batch(batch.requests))
+        } else {
+            Ok(SubmitPayload::Single(serde_json::from_value(value)?))
+        }
+    }
+}
+
+/// The uuid of a single request, or the batch id and the uuids of the requests of a
+/// batch in order.
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SubmitResponse {
+    Single(uuid::Uuid),
+    Batch {
+        batch_id: uuid::Uuid,
+        uuids: Vec<uuid::Uuid>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum EndpointType {
     Celo,
@@ -48,6 +196,13 @@ pub enum ProofRequest {
         circuit: Circuit,
         endpoint_type: EndpointType,
         endpoint: String,
+        /// Scope or user id of the verifying app, used to look the proofs up later.
+        #[serde(default)]
+        identifier: Option<String>,
+        /// Secret the verifying app hands out with its identifier, only those who know it
+        /// can look the proofs up.
+        #[serde(default)]
+        identifier_key: Option<String>,
     },
 }
 
@@ -59,18 +214,35 @@ impl ProofRequest {
             ProofRequest::Disclose { circuit, .. } => circuit,
         }
     }
+
+    pub fn identifier(&self) -> Option<&str> {
+        match self {
+            ProofRequest::Disclose { identifier, .. } => identifier.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn identifier_key(&self) -> Option<&str> {
+        match self {
+            ProofRequest::Disclose { identifier_key, .. } => identifier_key.as_deref(),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Clone)]
+//stored as a SMALLINT
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[repr(i16)]
 pub enum ProofType {
-    Register,
-    Dsc,
-    Disclose,
+    Register = 0,
+    Dsc = 1,
+    Disclose = 2,
 }
 
-impl Into<ProofType> for &ProofRequest {
-    fn into(self) -> ProofType {
-        match self {
+impl From<&ProofRequest> for ProofType {
+    fn from(val: &ProofRequest) -> Self {
+        match val {
             ProofRequest::Register { .. } => ProofType::Register,
             ProofRequest::Dsc { .. } => ProofType::Dsc,
             ProofRequest::Disclose { .. } => ProofType::Disclose,
@@ -87,32 +259,3 @@ impl std::fmt::Display for ProofType {
         }
     }
 }
-
-impl Into<i32> for &ProofType {
-    fn into(self) -> i32 {
-        match self {
-            ProofType::Register => 0,
-            ProofType::Dsc => 1,
-            ProofType::Disclose => 2,
-        }
-    }
-}
-
-impl TryFrom<i32> for ProofType {
-    type Error = ();
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(ProofType::Register),
-            1 => Ok(ProofType::Dsc),
-            2 => Ok(ProofType::Disclose),
-            _ => Err(()),
-        }
-    }
-}
-
-impl Into<i32> for ProofRequest {
-    fn into(self) -> i32 {
-        let proof_type: ProofType = (&self).into();
-        (&proof_type).into()
-    }
-}
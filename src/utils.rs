@@ -1,9 +1,23 @@
-use crate::db::fail_proof;
+use crate::db::ProofStore;
+use crate::error::Error;
+use crate::store::JobStore;
 use aes_gcm::aead::Aead;
 use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
 use aws_nitro_enclaves_nsm_api::api::{ErrorCode, Request, Response};
 use aws_nitro_enclaves_nsm_api::driver::nsm_process_request;
 use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::io::AsyncWriteExt;
+
+//set once at startup, before any request is accepted
+static SCRATCH_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+const MIN_IDENTIFIER_KEY_LENGTH: usize = 16;
 
 pub fn decrypt(
     key: [u8; 32],
@@ -30,8 +44,126 @@ pub fn decrypt(
     }
 }
 
+/// Creates the folder that holds the tmp folders of every request, optionally backed by a
+/// tmpfs of `tmpfs_size_mb` so that passport data never reaches the disk.
+pub fn init_scratch_dir(path: &str, tmpfs_size_mb: Option<u64>) -> std::io::Result<()> {
+    let path = PathBuf::from(path);
+    if !path.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&path)?;
+    }
+    //absolute paths are resolved through the tmpfs once it is mounted
+    let path = std::fs::canonicalize(path)?;
+
+    if let Some(size_mb) = tmpfs_size_mb {
+        //also checked by the config, this one sees through symlinks
+        if std::env::current_dir()?.starts_with(&path) {
+            return Err(std::io::Error::other(
+                "the tmpfs would be mounted over the working directory",
+            ));
+        }
+        mount_tmpfs(&path, size_mb)?;
+    }
+
+    SCRATCH_DIR
+        .set(path)
+        .map_err(|_| std::io::Error::other("scratch dir already set"))
+}
+
+fn mount_tmpfs(path: &Path, size_mb: u64) -> std::io::Result<()> {
+    let target = CString::new(path.as_os_str().as_bytes())?;
+    let options = CString::new(format!("size={}m,mode=0700", size_mb))?;
+    // SAFETY: every pointer is to a NUL terminated string that outlives the call, and
+    // tmpfs reads its data argument as such a string of comma separated options.
+    let res = unsafe {
+        libc::mount(
+            c"tmpfs".as_ptr(),
+            target.as_ptr(),
+            c"tmpfs".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            options.as_ptr().cast(),
+        )
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn get_scratch_dir() -> &'static Path {
+    SCRATCH_DIR.get().map_or(Path::new("."), PathBuf::as_path)
+}
+
 pub fn get_tmp_folder_path(uuid: &String) -> String {
-    format!("./tmp_{}", uuid)
+    get_scratch_dir()
+        .join(format!("tmp_{}", uuid))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Lists the tmp folders in the scratch dir along with the request they belong to.
+pub async fn list_tmp_folders() -> Vec<(Option<uuid::Uuid>, PathBuf)> {
+    let mut entries = match tokio::fs::read_dir(get_scratch_dir()).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!(error = %e, "could not list tmp folders");
+            return Vec::new();
+        }
+    };
+
+    let mut folders = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name();
+        if let Some(uuid) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix("tmp_"))
+        {
+            folders.push((uuid.parse().ok(), entry.path()));
+        }
+    }
+    folders
+}
+
+/// Removes every tmp folder, including those of requests that are still in flight.
+pub async fn remove_tmp_folders() {
+    for (_, path) in list_tmp_folders().await {
+        let _ = remove_tmp_folder(&path).await;
+    }
+}
+
+/// Overwrites every file in a tmp folder with zeros before deleting it, since the inputs
+/// and witnesses are derived from passport data.
+pub async fn remove_tmp_folder(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let mut folders = vec![path.as_ref().to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let mut entries = tokio::fs::read_dir(&folder).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                folders.push(entry.path());
+            } else if file_type.is_file() {
+                if let Err(e) = wipe_file(&entry.path()).await {
+                    tracing::warn!(error = %e, path = %entry.path().display(), "could not wipe file");
+                }
+            }
+        }
+    }
+    tokio::fs::remove_dir_all(path).await
+}
+
+async fn wipe_file(path: &Path) -> std::io::Result<()> {
+    static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    let mut remaining = file.metadata().await?.len();
+    while remaining > 0 {
+        let chunk = remaining.min(ZEROS.len() as u64) as usize;
+        file.write_all(&ZEROS[..chunk]).await?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all().await
 }
 
 pub fn get_attestation(
@@ -41,9 +173,9 @@ pub fn get_attestation(
     public_key: Option<Vec<u8>>,
 ) -> Result<Vec<u8>, ErrorCode> {
     let request = Request::Attestation {
-        user_data: user_data.map(|buf| ByteBuf::from(buf)),
-        nonce: nonce.map(|buf| ByteBuf::from(buf)),
-        public_key: public_key.map(|buf| ByteBuf::from(buf)),
+        user_data: user_data.map(ByteBuf::from),
+        nonce: nonce.map(ByteBuf::from),
+        public_key: public_key.map(ByteBuf::from),
     };
 
     match nsm_process_request(fd, request) {
@@ -53,10 +185,76 @@ pub fn get_attestation(
     }
 }
 
-pub async fn cleanup(uuid: uuid::Uuid, pool: &sqlx::Pool<sqlx::Postgres>, reason: String) {
+//the client derives the same token from its side of the ecdh agreement
+pub fn get_cancel_token(shared_secret: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(shared_secret);
+    hasher.update(b"cancel");
+    hasher.finalize().to_vec()
+}
+
+//the nonce and tag of a payload that could be decrypted have a fixed length, so the
+//concatenation is unambiguous
+pub fn get_payload_digest(nonce: &[u8], cipher_text: &[u8], auth_tag: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(nonce);
+    hasher.update(auth_tag);
+    hasher.update(cipher_text);
+    hasher.finalize().to_vec()
+}
+
+//the key is not stored, only its hash is compared
+pub fn get_identifier_key_hash(identifier_key: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(identifier_key.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+//the identifier column is a VARCHAR(255)
+pub fn check_identifier(identifier: &str) -> Result<(), Error> {
+    if identifier.is_empty() {
+        return Err(Error::InvalidIdentifier("must not be empty".to_string()));
+    }
+    if identifier.chars().count() > 255 {
+        return Err(Error::InvalidIdentifier(
+            "must be at most 255 characters".to_string(),
+        ));
+    }
+    if identifier.chars().any(char::is_control) {
+        return Err(Error::InvalidIdentifier(
+            "must not contain control characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+//anyone guessing the key could list the requests of the identifier
+pub fn check_identifier_key(identifier_key: &str) -> Result<(), Error> {
+    if identifier_key.chars().count() < MIN_IDENTIFIER_KEY_LENGTH {
+        return Err(Error::InvalidIdentifier(format!(
+            "the key must be at least {} characters",
+            MIN_IDENTIFIER_KEY_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+pub async fn cleanup(uuid: uuid::Uuid, db: &dyn ProofStore, jobs: &JobStore, error: &Error) {
+    //cancelled requests have already been marked by the cancel request
+    if jobs.is_cancelled(&uuid).await {
+        tracing::info!("request cancelled");
+    } else {
+        tracing::error!(code = error.code(), error = %error, "request failed");
+        metrics::counter!("requests_failed_total", "code" => error.code().to_string()).increment(1);
+        let _ = db.fail_proof(uuid, error).await;
+    }
+    discard_job(uuid, jobs).await;
+}
+
+pub async fn discard_job(uuid: uuid::Uuid, jobs: &JobStore) {
     let tmp_folder = get_tmp_folder_path(&uuid.to_string());
-    let _ = fail_proof(uuid, &pool, reason).await;
-    let _ = tokio::fs::remove_dir_all(tmp_folder).await;
+    jobs.remove_job(&uuid).await;
+    let _ = remove_tmp_folder(tmp_folder).await;
 }
 
 pub unsafe fn nsm_get_random(fd: i32, buf: *mut u8, buf_len: &mut usize) -> ErrorCode {